@@ -0,0 +1,63 @@
+//! Measures route lookup against routers of growing size.
+//!
+//! Run with `cargo bench --bench router`. The time per lookup should stay roughly the same from
+//! the smallest to the largest router: the resources differ in their static segments, so lookup
+//! follows the path without trying other branches, and only depends on the length of the path.
+
+use std::{hint::black_box, time::Instant};
+
+use espresso::{
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+    router::{RouteMatch, Router},
+};
+
+const LOOKUPS: usize = 200_000;
+
+fn build_router(resources: usize) -> Router {
+    let mut router = Router::new();
+    for i in 0..resources {
+        let base = format!("/api/v1/resource{i}");
+        let noop = |_req: &EspressoRequest, _res: &mut EspressoResponse| {};
+        router.get(&base, noop);
+        router.post(&base, noop);
+        router.get(&format!("{base}/:id"), noop);
+        router.put(&format!("{base}/:id"), noop);
+        router.get(&format!("{base}/:id/children/*rest"), noop);
+    }
+    router
+}
+
+fn main() {
+    println!(
+        "{:>8} {:>14} {:>14}",
+        "routes", "static ns/op", "param ns/op"
+    );
+    for resources in [10, 100, 1_000, 5_000] {
+        let router = build_router(resources);
+        let last = resources - 1;
+        let static_path = format!("/api/v1/resource{last}");
+        let param_path = format!("/api/v1/resource{last}/42/children/a/b");
+
+        let static_ns = time_lookups(&router, &static_path);
+        let param_ns = time_lookups(&router, &param_path);
+        println!(
+            "{:>8} {:>14.1} {:>14.1}",
+            resources * 5,
+            static_ns,
+            param_ns
+        );
+    }
+}
+
+/// Returns the average time of a lookup in nanoseconds.
+fn time_lookups(router: &Router, path: &str) -> f64 {
+    let start = Instant::now();
+    for _ in 0..LOOKUPS {
+        match router.find(black_box(&RequestMethod::GET), black_box(path)) {
+            RouteMatch::Found { .. } => {}
+            _ => panic!("{path} should be routed"),
+        }
+    }
+    start.elapsed().as_nanos() as f64 / LOOKUPS as f64
+}
@@ -0,0 +1,235 @@
+use std::{
+    fs::File,
+    io::{self, IoSlice, Read, Write},
+    path::Path,
+};
+
+use crate::response::write_all_vectored;
+
+/// Writes the body of a streamed response. See [`Body::stream`].
+pub type StreamFn = Box<dyn FnOnce(&mut BodyWriter<'_>) -> io::Result<()> + Send>;
+
+/// The body of a response.
+///
+/// Bytes are kept in memory and sent in one go. A reader is copied onto the connection, and a
+/// stream is written by a function, while the response is written. Either is framed with its
+/// length as `Content-Length` when the length is known, and with `Transfer-Encoding: chunked`
+/// when it isn't.
+pub enum Body {
+    Bytes(Vec<u8>),
+    Reader {
+        reader: Box<dyn Read + Send>,
+        length: Option<u64>,
+    },
+    Stream {
+        write: StreamFn,
+        length: Option<u64>,
+    },
+}
+
+impl Body {
+    /// A body read from `reader` until it ends. Its length isn't known up front, so it is sent
+    /// chunked.
+    pub fn reader(reader: impl Read + Send + 'static) -> Body {
+        Body::Reader {
+            reader: Box::new(reader),
+            length: None,
+        }
+    }
+
+    /// A body of the first `length` bytes of `reader`.
+    pub fn sized_reader(reader: impl Read + Send + 'static, length: u64) -> Body {
+        Body::Reader {
+            reader: Box::new(reader),
+            length: Some(length),
+        }
+    }
+
+    /// A body written by `write` once the status line and headers have been sent, so a handler
+    /// can produce a large body piece by piece, or over time, without holding it in memory.
+    ///
+    /// Every write to the [`BodyWriter`] is sent as one chunk straight away, so wrap it in a
+    /// [`std::io::BufWriter`] when writing many small pieces. If `write` fails, the connection
+    /// is closed, as the client can't tell the body is incomplete otherwise.
+    pub fn stream(
+        write: impl FnOnce(&mut BodyWriter<'_>) -> io::Result<()> + Send + 'static,
+    ) -> Body {
+        Body::Stream {
+            write: Box::new(write),
+            length: None,
+        }
+    }
+
+    /// Like [`Body::stream`], for a body of exactly `length` bytes, which is sent with a
+    /// `Content-Length` instead of in chunks.
+    pub fn sized_stream(
+        length: u64,
+        write: impl FnOnce(&mut BodyWriter<'_>) -> io::Result<()> + Send + 'static,
+    ) -> Body {
+        Body::Stream {
+            write: Box::new(write),
+            length: Some(length),
+        }
+    }
+
+    /// A body of the contents of `file`, from its current position, with the length of the file.
+    pub fn file(file: File) -> io::Result<Body> {
+        let length = file.metadata()?.len();
+        Ok(Body::sized_reader(file, length))
+    }
+
+    /// A body of the contents of the file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Body> {
+        Body::file(File::open(path)?)
+    }
+
+    /// The length of the body in bytes. `None` for a reader or stream of unknown length.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader { length, .. } | Body::Stream { length, .. } => *length,
+        }
+    }
+
+    /// Whether the body is known to be empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The body, if it is held in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Reader { .. } | Body::Stream { .. } => None,
+        }
+    }
+
+    /// Adds `bytes` to the end of the body. For a reader or a stream they are sent once it has
+    /// ended.
+    pub fn append(&mut self, bytes: &[u8]) {
+        match self {
+            Body::Bytes(data) => data.extend_from_slice(bytes),
+            Body::Reader { reader, length } => {
+                let head = std::mem::replace(reader, Box::new(io::empty()));
+                *reader = Box::new(head.chain(io::Cursor::new(bytes.to_vec())));
+                *length = length.map(|length| length + bytes.len() as u64);
+            }
+            Body::Stream { write, length } => {
+                let head = std::mem::replace(write, Box::new(|_| Ok(())));
+                let tail = bytes.to_vec();
+                *write = Box::new(move |out| {
+                    head(out)?;
+                    out.write_all(&tail)
+                });
+                *length = length.map(|length| length + bytes.len() as u64);
+            }
+        }
+    }
+}
+
+impl Default for Body {
+    fn default() -> Body {
+        Body::Bytes(Vec::new())
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Body {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Body {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+/// How the body of a response is delimited on the connection.
+pub(crate) enum Framing {
+    /// By a `Content-Length`; holds the number of bytes still to be written.
+    Length(u64),
+    Chunked,
+    /// By closing the connection, or by framing the response itself set up.
+    Raw,
+}
+
+/// Writes the body of a response onto the connection, framed as the response headers announced.
+pub struct BodyWriter<'a> {
+    conn: &'a mut dyn Write,
+    framing: Framing,
+}
+
+impl<'a> BodyWriter<'a> {
+    pub(crate) fn new(conn: &'a mut dyn Write, framing: Framing) -> BodyWriter<'a> {
+        BodyWriter { conn, framing }
+    }
+
+    /// Ends the body: sends the last chunk of a chunked body, or fails if fewer bytes were
+    /// written than its length.
+    pub(crate) fn finish(self) -> io::Result<()> {
+        match self.framing {
+            Framing::Chunked => self.conn.write_all(b"0\r\n\r\n")?,
+            Framing::Length(0) | Framing::Raw => {}
+            Framing::Length(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "response body ended before its length",
+                ))
+            }
+        }
+        self.conn.flush()
+    }
+}
+
+impl Write for BodyWriter<'_> {
+    /// Writes `buf`. In a chunked body it is sent whole as one chunk.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would end the body.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match &mut self.framing {
+            Framing::Chunked => {
+                let size = format!("{:X}\r\n", buf.len());
+                write_all_vectored(
+                    &mut self.conn,
+                    &mut [
+                        IoSlice::new(size.as_bytes()),
+                        IoSlice::new(buf),
+                        IoSlice::new(b"\r\n"),
+                    ],
+                )?;
+                Ok(buf.len())
+            }
+            Framing::Length(remaining) => {
+                if buf.len() as u64 > *remaining {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "response body is longer than its length",
+                    ));
+                }
+                let wrote = self.conn.write(buf)?;
+                *remaining -= wrote as u64;
+                Ok(wrote)
+            }
+            Framing::Raw => self.conn.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.conn.flush()
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    io,
+    net::{SocketAddr, TcpListener, ToSocketAddrs},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    config::EspressoConfig,
+    espresso::Espresso,
+    threads::{stream_threads, Executor, TPool},
+};
+
+/// Sets up an [`Espresso`] server before binding it: how many workers serve connections, on
+/// which [`TPool`], with which limits and timeouts.
+pub struct EspressoBuilder {
+    config: EspressoConfig,
+    workers: usize,
+    pool: fn(usize) -> Box<dyn Executor>,
+    backlog: u32,
+    engine: Engine,
+    event_loops: usize,
+}
+
+/// How a server waits for requests on its connections. Handlers see no difference.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Engine {
+    /// Each connection has a worker of its own, blocked on it from one request to the next.
+    #[default]
+    Threads,
+    /// A few event loop threads watch every connection at once with epoll, reading requests
+    /// without blocking and handing them to the workers once read in full. Thousands of mostly
+    /// idle connections then take no more than a few threads.
+    ///
+    /// A request to a route that streams its body, and a response that is streamed, read from a
+    /// file, long-lived or an upgrade, have the connection served by a worker in blocking mode
+    /// for as long as they take. Since the event loops hand requests to the pool, a
+    /// [`crate::threads::pigeonhole_threads::ThreadPool`] with every worker busy holds up all the
+    /// connections of a loop.
+    #[cfg(target_os = "linux")]
+    Epoll,
+}
+
+fn new_pool<P: TPool + Send + Sync + 'static>(workers: usize) -> Box<dyn Executor> {
+    Box::new(P::new(workers))
+}
+
+impl Default for EspressoBuilder {
+    fn default() -> EspressoBuilder {
+        EspressoBuilder {
+            config: EspressoConfig::default(),
+            workers: 100,
+            pool: new_pool::<stream_threads::ThreadPool>,
+            backlog: 128,
+            engine: Engine::default(),
+            event_loops: thread::available_parallelism().map_or(1, |cores| cores.get().min(4)),
+        }
+    }
+}
+
+impl EspressoBuilder {
+    pub fn new() -> EspressoBuilder {
+        EspressoBuilder::default()
+    }
+
+    /// How many connections are served at once, or with [`Engine::Epoll`], how many requests
+    /// are handled at once. Defaults to 100.
+    pub fn workers(mut self, workers: usize) -> EspressoBuilder {
+        self.workers = workers;
+        self
+    }
+
+    /// The thread pool the workers run on. Defaults to [`stream_threads::ThreadPool`].
+    pub fn pool<P: TPool + Send + Sync + 'static>(mut self) -> EspressoBuilder {
+        self.pool = new_pool::<P>;
+        self
+    }
+
+    /// How the server waits for requests. Defaults to [`Engine::Threads`].
+    pub fn engine(mut self, engine: Engine) -> EspressoBuilder {
+        self.engine = engine;
+        self
+    }
+
+    /// How many event loop threads [`Engine::Epoll`] runs. Defaults to the number of cores, up
+    /// to 4.
+    pub fn event_loops(mut self, event_loops: usize) -> EspressoBuilder {
+        self.event_loops = event_loops;
+        self
+    }
+
+    /// How many connections the operating system queues up before they are accepted. Defaults
+    /// to 128. Only Linux takes it into account; elsewhere the default of the standard library
+    /// applies.
+    pub fn backlog(mut self, backlog: u32) -> EspressoBuilder {
+        self.backlog = backlog;
+        self
+    }
+
+    /// Replaces every setting of [`EspressoConfig`] at once.
+    pub fn config(mut self, config: EspressoConfig) -> EspressoBuilder {
+        self.config = config;
+        self
+    }
+
+    /// See [`EspressoConfig::max_body_size`].
+    pub fn max_body_size(mut self, max_body_size: usize) -> EspressoBuilder {
+        self.config.max_body_size = max_body_size;
+        self
+    }
+
+    /// See [`EspressoConfig::max_chunk_extensions`].
+    pub fn max_chunk_extensions(mut self, max_chunk_extensions: usize) -> EspressoBuilder {
+        self.config.max_chunk_extensions = max_chunk_extensions;
+        self
+    }
+
+    /// See [`EspressoConfig::max_request_line`].
+    pub fn max_request_line(mut self, max_request_line: usize) -> EspressoBuilder {
+        self.config.max_request_line = max_request_line;
+        self
+    }
+
+    /// See [`EspressoConfig::max_header_size`].
+    pub fn max_header_size(mut self, max_header_size: usize) -> EspressoBuilder {
+        self.config.max_header_size = max_header_size;
+        self
+    }
+
+    /// See [`EspressoConfig::max_message_size`].
+    pub fn max_message_size(mut self, max_message_size: usize) -> EspressoBuilder {
+        self.config.max_message_size = max_message_size;
+        self
+    }
+
+    /// See [`EspressoConfig::idle_timeout`].
+    pub fn idle_timeout(mut self, idle_timeout: Option<Duration>) -> EspressoBuilder {
+        self.config.idle_timeout = idle_timeout;
+        self
+    }
+
+    /// See [`EspressoConfig::header_timeout`].
+    pub fn header_timeout(mut self, header_timeout: Option<Duration>) -> EspressoBuilder {
+        self.config.header_timeout = header_timeout;
+        self
+    }
+
+    /// See [`EspressoConfig::max_requests_per_connection`].
+    pub fn max_requests_per_connection(mut self, max_requests: Option<usize>) -> EspressoBuilder {
+        self.config.max_requests_per_connection = max_requests;
+        self
+    }
+
+    /// See [`EspressoConfig::max_long_lived`].
+    pub fn max_long_lived(mut self, max_long_lived: usize) -> EspressoBuilder {
+        self.config.max_long_lived = max_long_lived;
+        self
+    }
+
+    /// See [`EspressoConfig::shutdown_timeout`].
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> EspressoBuilder {
+        self.config.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Binds the server to the first of the addresses `addr` resolves to that it can listen on.
+    /// Port 0 picks a free port, which [`Espresso::local_addr`] tells.
+    ///
+    /// Fails if no address can be bound, or with `InvalidInput` if there are no workers or no
+    /// event loops.
+    pub fn bind(self, addr: impl ToSocketAddrs) -> io::Result<Espresso> {
+        if self.workers == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A server needs at least one worker.",
+            ));
+        }
+        if self.event_loops == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "A server needs at least one event loop.",
+            ));
+        }
+        let mut last_err = None;
+        for addr in addr.to_socket_addrs()? {
+            match listen(addr, self.backlog) {
+                Ok(listener) => {
+                    let pool = (self.pool)(self.workers);
+                    return Ok(Espresso::from_parts(
+                        listener,
+                        self.config,
+                        pool,
+                        self.engine,
+                        self.event_loops,
+                    ));
+                }
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "The address didn't resolve to anything.",
+            )
+        }))
+    }
+}
+
+/// Binds a listener to `addr`, as [`TcpListener::bind`] does but with a backlog of `backlog`.
+#[cfg(target_os = "linux")]
+fn listen(addr: SocketAddr, backlog: u32) -> io::Result<TcpListener> {
+    use std::{mem, os::fd::FromRawFd};
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(result)
+    }
+
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = check(unsafe { libc::socket(family, libc::SOCK_STREAM | libc::SOCK_CLOEXEC, 0) })?;
+    // Owned from here on, so the socket is closed if anything below fails.
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    // Lets a restarted server bind while connections of the last one are in TIME_WAIT, as the
+    // standard library does.
+    let reuse: libc::c_int = 1;
+    check(unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            (&reuse as *const libc::c_int).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })?;
+
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let length = match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe {
+                &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in>()
+            };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe {
+                &mut *(&mut storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in6>()
+            };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    check(unsafe {
+        libc::bind(
+            fd,
+            (&storage as *const libc::sockaddr_storage).cast(),
+            length as libc::socklen_t,
+        )
+    })?;
+    let backlog = backlog.min(libc::c_int::MAX as u32) as libc::c_int;
+    check(unsafe { libc::listen(fd, backlog) })?;
+    Ok(listener)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn listen(addr: SocketAddr, _backlog: u32) -> io::Result<TcpListener> {
+    TcpListener::bind(addr)
+}
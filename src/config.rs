@@ -0,0 +1,58 @@
+use std::time::Duration;
+
+use crate::parser::{MAX_HEADER_SECTION, MAX_REQUEST_LINE};
+
+/// Settings for how an [`crate::espresso::Espresso`] server reads requests.
+#[derive(Clone, Debug)]
+pub struct EspressoConfig {
+    /// Largest request body accepted, in bytes. Larger requests are answered with
+    /// `413 Content Too Large` and the connection is closed.
+    pub max_body_size: usize,
+    /// Most bytes of chunk extensions a chunked request body may carry, across all its chunks.
+    /// Bodies with more are answered with `413 Content Too Large`, since the extensions are kept
+    /// along with the body.
+    pub max_chunk_extensions: usize,
+    /// Longest request line accepted, in bytes. Longer ones are answered with
+    /// `414 URI Too Long`.
+    pub max_request_line: usize,
+    /// Largest header section accepted, in bytes. Larger ones are answered with
+    /// `431 Request Header Fields Too Large`.
+    pub max_header_size: usize,
+    /// Largest WebSocket message accepted, in bytes, counting every fragment. Larger messages
+    /// close the connection with [`crate::websocket::CloseCode::MESSAGE_TOO_BIG`].
+    pub max_message_size: usize,
+    /// Longest the server waits for the client to send anything: the next request on a
+    /// kept-alive connection, or more of a request body. An idle connection is closed once it
+    /// passes, freeing its worker. `None` waits forever.
+    pub idle_timeout: Option<Duration>,
+    /// Longest the client may take to send the request line and headers, counted from their
+    /// first byte. Slower requests are answered with `408 Request Timeout`, so a client
+    /// trickling its headers in can't hold on to a worker. `None` waits forever.
+    pub header_timeout: Option<Duration>,
+    /// Most requests answered on one connection. The response to the last one carries
+    /// `Connection: close`. `None` for no limit.
+    pub max_requests_per_connection: Option<usize>,
+    /// Most connections that are upgraded, or writing a long-lived response such as
+    /// [`crate::response::EspressoResponse::sse`], at once. Each of them has a thread of its own,
+    /// so those over the limit are answered with `503 Service Unavailable` and closed instead.
+    pub max_long_lived: usize,
+    /// Longest a shutdown waits for the requests in flight before closing their connections.
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for EspressoConfig {
+    fn default() -> EspressoConfig {
+        EspressoConfig {
+            max_body_size: 1024 * 1024,
+            max_chunk_extensions: 16 * 1024,
+            max_request_line: MAX_REQUEST_LINE,
+            max_header_size: MAX_HEADER_SECTION,
+            max_message_size: 16 * 1024 * 1024,
+            idle_timeout: Some(Duration::from_secs(5)),
+            header_timeout: Some(Duration::from_secs(10)),
+            max_requests_per_connection: Some(1000),
+            max_long_lived: 1024,
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
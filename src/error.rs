@@ -1,12 +1,158 @@
+use std::fmt;
 
+use crate::{response::EspressoResponseError, status::StatusCode, websocket::CloseCode};
+
+#[derive(Debug)]
 pub enum EspressoRequestError {
     MalformedRequest(String),
     IncompleteRequest(String),
-
+    /// The request line is longer than the server accepts.
+    UriTooLong,
+    /// The header section is larger than the server accepts.
+    HeaderTooLarge,
+    /// The request uses an HTTP major version other than 1.
+    UnsupportedVersion(String),
+    /// The body is larger than [`crate::config::EspressoConfig::max_body_size`].
+    PayloadTooLarge,
+    /// The request uses a transfer coding other than chunked.
+    UnsupportedTransferEncoding(String),
+    /// The client stopped sending the request for longer than
+    /// [`crate::config::EspressoConfig::idle_timeout`], or took longer than
+    /// [`crate::config::EspressoConfig::header_timeout`] to send its headers.
+    Timeout,
 }
 
+#[derive(Debug)]
 pub enum EspressoProcessingError {
     HandleBeforeListen,
     FailedThreadPool,
-    ConnectionClosed
+    ConnectionClosed,
+}
+
+#[derive(Debug)]
+pub enum EspressoRouteError {
+    InvalidPattern(String),
+    /// Two routes would match the same requests.
+    Conflict(String),
+}
+
+#[derive(Debug)]
+pub enum EspressoWebSocketError {
+    /// Reading from or writing to the connection failed.
+    Io(std::io::Error),
+    /// The client broke the protocol, and the connection was closed with the code.
+    ProtocolError(CloseCode, String),
+    /// The connection has been closed, or is being closed, so no more messages can be sent
+    /// or received.
+    ConnectionClosed,
+}
+
+impl From<std::io::Error> for EspressoWebSocketError {
+    fn from(err: std::io::Error) -> EspressoWebSocketError {
+        EspressoWebSocketError::Io(err)
+    }
+}
+
+/// An error raised while handling a request, carrying the status code it should be answered with.
+#[derive(Debug)]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> HttpError {
+        HttpError {
+            status,
+            message: message.into(),
+        }
+    }
+
+    /// Like [`HttpError::new`], with the status given as a number. Returns an error for codes
+    /// outside 100 to 999.
+    pub fn try_new(
+        status: usize,
+        message: impl Into<String>,
+    ) -> Result<HttpError, EspressoResponseError> {
+        Ok(HttpError::new(StatusCode::try_from(status)?, message))
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> HttpError {
+        HttpError::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> HttpError {
+        HttpError::new(StatusCode::NOT_FOUND, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> HttpError {
+        HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.status, self.message)
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+impl From<EspressoRequestError> for HttpError {
+    fn from(err: EspressoRequestError) -> HttpError {
+        match err {
+            EspressoRequestError::MalformedRequest(message)
+            | EspressoRequestError::IncompleteRequest(message) => HttpError::bad_request(message),
+            EspressoRequestError::UriTooLong => {
+                HttpError::new(StatusCode::URI_TOO_LONG, "Request line too long")
+            }
+            EspressoRequestError::HeaderTooLarge => HttpError::new(
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                "Request header fields too large",
+            ),
+            EspressoRequestError::UnsupportedVersion(version) => HttpError::new(
+                StatusCode::HTTP_VERSION_NOT_SUPPORTED,
+                format!("{version} is not supported"),
+            ),
+            EspressoRequestError::PayloadTooLarge => {
+                HttpError::new(StatusCode::CONTENT_TOO_LARGE, "Request body too large")
+            }
+            EspressoRequestError::Timeout => {
+                HttpError::new(StatusCode::REQUEST_TIMEOUT, "Request timed out")
+            }
+            EspressoRequestError::UnsupportedTransferEncoding(encoding) => HttpError::new(
+                StatusCode::NOT_IMPLEMENTED,
+                format!("Transfer-Encoding {encoding} is not supported"),
+            ),
+        }
+    }
+}
+
+impl From<EspressoProcessingError> for HttpError {
+    fn from(err: EspressoProcessingError) -> HttpError {
+        HttpError::internal(format!("{err:?}"))
+    }
+}
+
+impl From<std::io::Error> for HttpError {
+    fn from(err: std::io::Error) -> HttpError {
+        HttpError::internal(err.to_string())
+    }
+}
+
+/// What a handler or middleware may return: nothing, or a `Result` whose error converts into an [`HttpError`].
+pub trait HandlerResult {
+    fn into_result(self) -> Result<(), HttpError>;
+}
+
+impl HandlerResult for () {
+    fn into_result(self) -> Result<(), HttpError> {
+        Ok(())
+    }
+}
+
+impl<E: Into<HttpError>> HandlerResult for Result<(), E> {
+    fn into_result(self) -> Result<(), HttpError> {
+        self.map_err(Into::into)
+    }
 }
@@ -1,84 +1,496 @@
-use core::panic;
 use std::{
-    collections::HashMap,
-    net::{TcpListener, TcpStream},
-    sync::Arc,
+    io,
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+    time::Instant,
 };
 
 use crate::{
-    error::EspressoProcessingError,
+    body::Body,
+    builder::{Engine, EspressoBuilder},
+    config::EspressoConfig,
+    error::{EspressoProcessingError, EspressoRequestError, HandlerResult, HttpError},
+    middleware::{default_error_handler, Next},
     request::{EspressoRequest, EspressoStream, RequestMethod},
     response::EspressoResponse,
-    threads::{stream_threads::ThreadPool, TPool},
+    router::{RouteMatch, Router},
+    shutdown::{ShutdownHandle, ShutdownState, TrackedConnection},
+    status::StatusCode,
+    threads::Executor,
+    websocket::WebSocket,
 };
 
-pub type RequestHandler =
-    Box<dyn Fn(&EspressoRequest, &mut EspressoResponse) + Send + Sync + 'static>;
-pub type MethodHandlers = HashMap<String, Arc<RequestHandler>>;
+#[cfg(target_os = "linux")]
+use crate::event_loop;
+
+pub use crate::router::RequestHandler;
+
 pub struct Espresso {
     tcp_listener: TcpListener,
-    /// HM of Request Type => Pattern => Route handler
-    method_handlers: HashMap<RequestMethod, MethodHandlers>,
-    thread_pool: ThreadPool,
-    global_handlers: MethodHandlers,
+    router: Router,
+    config: EspressoConfig,
+    /// Taken once the server has shut down, which stops the workers.
+    thread_pool: Option<Arc<dyn Executor>>,
     internal: Option<Arc<EspressoInternal>>,
+    shutdown: Arc<ShutdownState>,
+    engine: Engine,
+    event_loops: usize,
 }
 
 /// Internal struct to hold ownership of the methods available to be after a `listen()` call.
 /// This is for cross-thread access purposes. We do not need mutability of the variables after `listen()`
-struct EspressoInternal {
-    all: Box<[(String, Arc<RequestHandler>)]>,
-    methods: HashMap<RequestMethod, HashMap<String, Arc<RequestHandler>>>,
+pub(crate) struct EspressoInternal {
+    pub(crate) router: Router,
+    pub(crate) config: EspressoConfig,
+    pub(crate) shutdown: Arc<ShutdownState>,
+    /// How many connections have a thread of their own, see [`EspressoConfig::max_long_lived`].
+    long_lived: AtomicUsize,
 }
 
-type EspressoMiddleware = Box<dyn FnMut(EspressoRequest) + Send + 'static>;
+/// The place of a connection among those with a thread of their own, given back when dropped.
+struct LongLivedSlot(Arc<EspressoInternal>);
 
-impl Espresso {
-    pub fn new(addr: &str) -> Espresso {
-        let tcp_listener: TcpListener = match TcpListener::bind(addr) {
-            Ok(listener) => listener,
-            Err(_) => {
-                panic!("Error occurred while binding to {addr}");
+impl LongLivedSlot {
+    /// Takes a place, unless [`EspressoConfig::max_long_lived`] are taken already.
+    fn claim(internal: &Arc<EspressoInternal>) -> Option<LongLivedSlot> {
+        internal
+            .long_lived
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |taken| {
+                (taken < internal.config.max_long_lived).then_some(taken + 1)
+            })
+            .ok()?;
+        Some(LongLivedSlot(Arc::clone(internal)))
+    }
+}
+
+impl Drop for LongLivedSlot {
+    fn drop(&mut self) {
+        self.0.long_lived.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl EspressoInternal {
+    /// Runs the middleware and handler matching the request and builds the response, without its
+    /// body for HEAD requests. The response says whether the connection stays open, which it
+    /// only does if `keep_alive` allows it and the server isn't shutting down.
+    /// A panic while handling the request is answered with `500 Internal Server Error` instead of
+    /// taking down the worker and the connection with it.
+    ///
+    /// `found` is the route of the request, if it was looked up while reading the request.
+    pub(crate) fn dispatch(
+        &self,
+        request: &mut EspressoRequest,
+        found: Option<RouteMatch>,
+        keep_alive: bool,
+    ) -> EspressoResponse {
+        let mut response = EspressoResponse::new();
+        let handled = panic::catch_unwind(AssertUnwindSafe(|| {
+            let found = found.unwrap_or_else(|| self.router.lookup(request));
+            self.router.handle_match(found, request, &mut response)
+        }));
+        if let Err(payload) = handled {
+            let message = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic payload");
+            default_error_handler(
+                HttpError::internal(format!("handler panicked: {message}")),
+                request,
+                &mut response,
+            );
+        }
+        let framed = response.headers.contains("Content-Length")
+            || response.headers.contains("Transfer-Encoding");
+        let http_1_0 = request.protocol_ver == "HTTP/1.0";
+        // The upgraded connection is the business of whoever takes it over.
+        if response.upgrade.is_none() {
+            // HTTP/1.0 clients don't know chunked bodies, so the end of the connection ends it.
+            let close_delimited = !framed && response.body.len().is_none() && http_1_0;
+            if !keep_alive
+                || self.shutdown.is_stopping()
+                || close_delimited
+                || !client_keeps_alive(request)
+                || response.headers.contains_token("Connection", "close")
+            {
+                response.set_header("Connection", "close");
+            } else if http_1_0 {
+                // HTTP/1.0 connections close after the response unless it says otherwise.
+                response.set_header("Connection", "keep-alive");
             }
+        }
+        if request.method == RequestMethod::HEAD {
+            // Send the headers a GET would get, including how the body it would have is framed.
+            if !response.headers.contains("Transfer-Encoding") {
+                match response.body.len() {
+                    Some(length) => response.set_header("Content-Length", &length.to_string()),
+                    None if framed => {}
+                    None if response.headers.contains_token("Connection", "close") => {}
+                    None => response.set_header("Transfer-Encoding", "chunked"),
+                }
+            }
+            response.body = Body::default();
+            response.head = true;
+            // Without a body there is nothing to keep writing, so it needs no thread of its own.
+            response.long_lived = false;
+        }
+        response
+    }
+
+    /// Answers the requests on `stream` until the client, an error or a shutdown ends the
+    /// connection.
+    fn serve(self: Arc<Self>, mut stream: EspressoStream, mut conn: TrackedConnection) {
+        // Shutting down closes connections between requests. One accepted before still gets its
+        // first request answered.
+        while stream.requests == 0 || conn.idle() {
+            match Arc::clone(&self).serve_one(stream, conn, None) {
+                Some(next) => (stream, conn) = next,
+                None => return,
+            }
+        }
+    }
+
+    /// Reads the next request on `stream` and answers it. Returns the connection if it carries on
+    /// with another request. `found` is the route of that request, if it was looked up already.
+    pub(crate) fn serve_one(
+        self: Arc<Self>,
+        mut stream: EspressoStream,
+        conn: TrackedConnection,
+        mut found: Option<RouteMatch>,
+    ) -> Option<(EspressoStream, TrackedConnection)> {
+        // Shutting down lets the request finish once any of it has arrived.
+        let next = stream.read_next(
+            || conn.busy(),
+            |request| {
+                found
+                    .get_or_insert_with(|| self.router.lookup(request))
+                    .streams_body()
+            },
+        );
+        match next {
+            Ok(Some(mut frame)) => {
+                let keep_alive = self
+                    .config
+                    .max_requests_per_connection
+                    .is_none_or(|max| stream.requests < max);
+                let response = self.dispatch(&mut frame.request, found, keep_alive);
+                self.answer(stream, frame.request, response, conn)
+            }
+            Ok(None) => None,
+            Err(err) => {
+                // The rest of the connection can't be read reliably, so answer and hang up.
+                let _ = stream.writer.write_response(error_response(err));
+                None
+            }
+        }
+    }
+
+    /// Sends `response` to `request`. Returns the connection if it carries on with another
+    /// request; upgraded and long-lived ones carry on in a thread of their own, up to
+    /// [`EspressoConfig::max_long_lived`] of them.
+    pub(crate) fn answer(
+        self: Arc<Self>,
+        mut stream: EspressoStream,
+        request: EspressoRequest,
+        mut response: EspressoResponse,
+        conn: TrackedConnection,
+    ) -> Option<(EspressoStream, TrackedConnection)> {
+        let upgrade = response
+            .upgrade
+            .take()
+            .filter(|_| response.status == StatusCode::SWITCHING_PROTOCOLS);
+        if upgrade.is_none() && !response.long_lived {
+            return Self::respond(&mut stream, response, &request).then_some((stream, conn));
+        }
+
+        let Some(slot) = LongLivedSlot::claim(&self) else {
+            let mut unavailable = EspressoResponse::new();
+            unavailable.set_status(StatusCode::SERVICE_UNAVAILABLE);
+            unavailable.send("Too many long-lived connections");
+            unavailable.set_header("Connection", "close");
+            let _ = stream.writer.write_response(unavailable);
+            return None;
+        };
+        let spawned = match upgrade {
+            // The connection speaks another protocol from now on, for as long as the handler
+            // keeps it open.
+            Some(upgrade) => thread::Builder::new().spawn(move || {
+                let _slot = slot;
+                let _conn = conn;
+                if stream.writer.write_response(response).is_ok() {
+                    upgrade(&request, stream);
+                }
+            }),
+            // Writing it could take forever, so free the worker: the response and the rest of
+            // the connection go on in a thread of their own.
+            None => thread::Builder::new().spawn(move || {
+                let _slot = slot;
+                if Self::respond(&mut stream, response, &request) {
+                    self.serve(stream, conn);
+                }
+            }),
         };
+        // The connection went down with the thread that didn't start.
+        if let Err(err) = spawned {
+            eprintln!("Unable to start a thread for a long-lived connection: {err}");
+        }
+        None
+    }
+
+    /// Writes `response` to `request`, returning whether the connection can carry on.
+    fn respond(
+        stream: &mut EspressoStream,
+        response: EspressoResponse,
+        request: &EspressoRequest,
+    ) -> bool {
+        let close = response.headers.contains_token("Connection", "close");
+        stream.writer.write_response(response).is_ok() && !close && stream.finish(request)
+    }
+}
+
+/// The answer to a request that couldn't be read, after which the connection is closed.
+pub(crate) fn error_response(err: EspressoRequestError) -> EspressoResponse {
+    let mut response = EspressoResponse::new();
+    let err = HttpError::from(err);
+    response.set_status(err.status);
+    response.send(&err.message);
+    response.set_header("Connection", "close");
+    response
+}
+
+/// Whether the client asked for the connection to stay open after the response: the default from
+/// HTTP/1.1 on, and what `Connection: keep-alive` asks of an HTTP/1.0 server.
+fn client_keeps_alive(request: &EspressoRequest) -> bool {
+    let headers = &request.headers;
+    if headers.contains_token("Connection", "close") {
+        return false;
+    }
+    request.protocol_ver != "HTTP/1.0" || headers.contains_token("Connection", "keep-alive")
+}
+
+impl Espresso {
+    /// Binds a server with the default settings to `addr`.
+    ///
+    /// # Panics
+    /// If `addr` can't be bound. [`Espresso::bind`] returns the error instead.
+    pub fn new(addr: &str) -> Espresso {
+        Espresso::bind(addr)
+            .unwrap_or_else(|err| panic!("Error occurred while binding to {addr}: {err}"))
+    }
+
+    /// Binds a server with the default settings to `addr`. See [`EspressoBuilder::bind`].
+    pub fn bind(addr: impl ToSocketAddrs) -> io::Result<Espresso> {
+        EspressoBuilder::new().bind(addr)
+    }
+
+    /// A builder for a server with other settings than the default ones.
+    pub fn builder() -> EspressoBuilder {
+        EspressoBuilder::new()
+    }
+
+    pub(crate) fn from_parts(
+        tcp_listener: TcpListener,
+        config: EspressoConfig,
+        thread_pool: Box<dyn Executor>,
+        engine: Engine,
+        event_loops: usize,
+    ) -> Espresso {
+        let shutdown = Arc::new(ShutdownState::new(tcp_listener.local_addr().ok()));
         Espresso {
             tcp_listener,
-            method_handlers: HashMap::new(),
-            thread_pool: ThreadPool::new(100),
-            global_handlers: HashMap::new(),
+            router: Router::new(),
+            config,
+            thread_pool: Some(Arc::from(thread_pool)),
             internal: None,
+            shutdown,
+            engine,
+            event_loops,
         }
     }
-    pub fn all(
+
+    /// The address the server listens on, with the port picked when it was bound to port 0.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp_listener.local_addr()
+    }
+    /// Registers a handler for every request method matching `pattern` that has no handler of its own.
+    /// See [`Router::try_route`] for the pattern syntax.
+    ///
+    /// # Panics
+    /// If `pattern` is invalid or conflicts with an existing route.
+    pub fn all<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.all(pattern, request_handler);
+    }
+
+    /// Registers a handler for `method` requests matching `pattern`. Use this for methods without
+    /// a method of their own here, including extension methods such as `PURGE`.
+    ///
+    /// # Panics
+    /// If `pattern` is invalid or conflicts with an existing route.
+    pub fn route<R: HandlerResult>(
+        &mut self,
+        method: RequestMethod,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.route(method, pattern, request_handler);
+    }
+
+    pub fn get<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.get(pattern, request_handler);
+    }
+
+    pub fn post<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.post(pattern, request_handler);
+    }
+
+    pub fn put<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.put(pattern, request_handler);
+    }
+
+    pub fn patch<R: HandlerResult>(
         &mut self,
         pattern: &str,
-        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) + Send + Sync + 'static,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
     ) {
-        self.global_handlers
-            .insert(pattern.to_string(), Arc::new(Box::new(request_handler)));
+        self.router.patch(pattern, request_handler);
     }
 
-    fn register_fn_handler(
+    pub fn delete<R: HandlerResult>(
         &mut self,
         pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.delete(pattern, request_handler);
+    }
+
+    pub fn options<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.options(pattern, request_handler);
+    }
+
+    pub fn head<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.router.head(pattern, request_handler);
+    }
+
+    /// Adds a WebSocket endpoint. See [`Router::ws`].
+    pub fn ws(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, WebSocket) + Send + Sync + 'static,
+    ) {
+        self.router.ws(pattern, handler);
+    }
+
+    /// Registers a handler that reads the request body itself. See [`Router::try_route_streaming`].
+    ///
+    /// # Panics
+    /// If `pattern` is invalid or conflicts with an existing route.
+    pub fn route_streaming<R: HandlerResult>(
+        &mut self,
         method: RequestMethod,
-        handle_func: impl (FnMut(&EspressoRequest, &mut EspressoResponse) -> ()) + Send + 'static,
+        pattern: &str,
+        request_handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
     ) {
+        self.router
+            .route_streaming(method, pattern, request_handler);
+    }
+
+    /// Mounts `router` under `prefix`. See [`Router::mount`].
+    ///
+    /// # Panics
+    /// If `prefix` is invalid or already has a router mounted.
+    pub fn mount(&mut self, prefix: &str, router: Router) {
+        self.router.mount(prefix, router);
+    }
+
+    /// The router holding the routes of the application, for registering routes with
+    /// [`Router::try_route`] and handling the conflict errors.
+    pub fn router(&mut self) -> &mut Router {
+        &mut self.router
     }
 
+    /// The settings the server reads requests with. Changes take effect on the next call to [`Espresso::listen`].
+    pub fn config(&mut self) -> &mut EspressoConfig {
+        &mut self.config
+    }
+
+    /// A handle for shutting the server down from another thread, or on a signal.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle::new(Arc::clone(&self.shutdown))
+    }
+
+    /// Serves requests on a thread of its own, returning a handle to shut the server down with.
+    pub fn spawn(mut self) -> ShutdownHandle {
+        let handle = self.shutdown_handle();
+        thread::spawn(move || self.listen());
+        handle
+    }
+
+    /// Serves requests until the server is shut down through a [`ShutdownHandle`]. Returns once
+    /// the connections have been closed and the workers have stopped.
+    ///
+    /// The routes are copied as they are when it is called, so the application keeps them.
     pub fn listen(&mut self) {
-        self.internal = Some(Arc::new(EspressoInternal {
-            all: {
-                let mut global_handles: Vec<(String, Arc<RequestHandler>)> = Vec::new();
-                let global_handler_map = self.global_handlers.clone();
-                for (key, value) in global_handler_map {
-                    global_handles.push((key, value));
+        let internal = Arc::new(EspressoInternal {
+            router: self.router.clone(),
+            config: self.config.clone(),
+            shutdown: Arc::clone(&self.shutdown),
+            long_lived: AtomicUsize::new(0),
+        });
+        self.internal = Some(Arc::clone(&internal));
+        match self.engine {
+            Engine::Threads => self.accept_connections(),
+            #[cfg(target_os = "linux")]
+            Engine::Epoll => {
+                if let Some(pool) = self.thread_pool.clone() {
+                    let listener = &self.tcp_listener;
+                    if let Err(err) = event_loop::run(internal, listener, pool, self.event_loops) {
+                        println!("Error while starting the event loops: {err}");
+                    }
                 }
-                global_handles.as_slice().into()
-            },
-            methods: self.method_handlers.clone(),
-        }));
+            }
+        }
+        self.shutdown
+            .drain(Instant::now() + self.config.shutdown_timeout);
+        // Dropping the pool waits for the workers to finish their connections.
+        drop(self.thread_pool.take());
+        self.shutdown.stopped();
+    }
+
+    /// Hands every connection accepted to a worker of its own, until the server shuts down.
+    fn accept_connections(&self) {
         for stream in self.tcp_listener.incoming() {
+            if self.shutdown.is_stopping() {
+                break;
+            }
             match stream {
                 Ok(stream) => {
                     let _ = self.handle_stream(stream);
@@ -98,40 +510,54 @@ impl Espresso {
             }
         });
 
-        self.thread_pool.exec(move || {
-            let mut stream = EspressoStream::new(tcp_stream);
-            // Cook up a new response in the thread
-            let global_handlers = &i.all;
-            loop {
-                if let Some(frame) = stream.next() {
-                    let request = frame.request;
-                    let mut response = EspressoResponse::new();
-
-                    for (l, handle_fn) in global_handlers {
-                        if request.resource.eq(l) {
-                            handle_fn(&request, &mut response);
-                        }
-                    }
-                    match request.method {
-                        RequestMethod::GET => {
-                            let rwrite = &mut stream.writer;
-                            rwrite.write_response(response);
-                        }
-                        RequestMethod::POST => todo!(),
-                        RequestMethod::PUT => todo!(),
-                        RequestMethod::DELETE => todo!(),
-                        RequestMethod::PATCH => todo!(),
-                        RequestMethod::OPTIONS => todo!(),
-                        RequestMethod::HEAD => todo!(),
-                    }
-                } else {
-                    break;
-                }
-            }
-        });
+        let Some(thread_pool) = &self.thread_pool else {
+            return Err(EspressoProcessingError::FailedThreadPool);
+        };
+        let Some(conn) = i.shutdown.track(&tcp_stream) else {
+            return Err(EspressoProcessingError::ConnectionClosed);
+        };
+        thread_pool.execute(Box::new(move || {
+            let stream = EspressoStream::with_config(tcp_stream, i.config.clone());
+            i.serve(stream, conn);
+        }));
         Ok(())
     }
 
     // Can't use `use` because it is a Rust language word.
-    // pub fn middleware(&mut self, middleware: EspressoMiddleware) -> () {}
+    /// Adds middleware that runs for every request. See [`Router::middleware`].
+    pub fn middleware<R: HandlerResult>(
+        &mut self,
+        middleware: impl Fn(&mut EspressoRequest, &mut EspressoResponse, Next) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.router.middleware(middleware);
+    }
+
+    /// Adds middleware that runs for requests under `prefix`. See [`Router::middleware_at`].
+    ///
+    /// # Panics
+    /// If `prefix` is invalid.
+    pub fn middleware_at<R: HandlerResult>(
+        &mut self,
+        prefix: &str,
+        middleware: impl Fn(&mut EspressoRequest, &mut EspressoResponse, Next) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.router.middleware_at(prefix, middleware);
+    }
+
+    /// Adds error middleware for every request. See [`Router::error_middleware`].
+    pub fn error_middleware<R: HandlerResult>(
+        &mut self,
+        middleware: impl Fn(HttpError, &EspressoRequest, &mut EspressoResponse) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.router.error_middleware(middleware);
+    }
 }
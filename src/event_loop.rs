@@ -0,0 +1,808 @@
+//! The epoll engine: a few event loop threads watch every connection at once, read requests
+//! without blocking, and only hand them to the workers once they have been read in full.
+//!
+//! A connection belongs to one loop at a time, or to the worker handling its request, which
+//! gives it back once the response has been written or queued.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    net::{TcpListener, TcpStream},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    body::Body,
+    config::EspressoConfig,
+    error::EspressoRequestError,
+    espresso::{error_response, EspressoInternal},
+    parser::{self, BodyFraming, ChunkedDecoder, RequestBody},
+    request::{self, lock, EspressoRequest, EspressoStream},
+    response::{EspressoResponse, ResponseWriter},
+    router::RouteMatch,
+    shutdown::TrackedConnection,
+    threads::Executor,
+};
+
+/// The token of the listening socket.
+const LISTENER: u64 = 0;
+/// The token of the eventfd workers wake a loop up through.
+const WAKER: u64 = 1;
+/// How often connections are checked for timeouts, which is as precise as these get.
+const SWEEP_INTERVAL: Duration = Duration::from_millis(100);
+/// The most read from a connection at once.
+const READ_SIZE: usize = 16 * 1024;
+/// The most a connection keeps of the capacity of its buffers between requests.
+const KEPT_CAPACITY: usize = 64 * 1024;
+
+const READABLE: u32 = libc::EPOLLIN as u32;
+const WRITABLE: u32 = libc::EPOLLOUT as u32;
+
+/// Serves the connections `listener` accepts on `event_loops` threads, until the server shuts
+/// down and its connections have been drained.
+pub(crate) fn run(
+    internal: Arc<EspressoInternal>,
+    listener: &TcpListener,
+    pool: Arc<dyn Executor>,
+    event_loops: usize,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let loops = (0..event_loops)
+        .map(|_| {
+            EventLoop::new(
+                Arc::clone(&internal),
+                listener.try_clone()?,
+                Arc::clone(&pool),
+            )
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    let loops: Vec<_> = loops
+        .into_iter()
+        .map(|event_loop| {
+            let shared = Arc::clone(&event_loop.shared);
+            (shared, thread::spawn(move || event_loop.run()))
+        })
+        .collect();
+
+    internal.shutdown.wait_for_shutdown();
+    internal
+        .shutdown
+        .drain(Instant::now() + internal.config.shutdown_timeout);
+    for (shared, _) in &loops {
+        shared.exit.store(true, Ordering::SeqCst);
+        shared.wake();
+    }
+    for (_, thread) in loops {
+        let _ = thread.join();
+    }
+    Ok(())
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(result)
+}
+
+/// An epoll instance, level-triggered: a connection is reported for as long as it is ready.
+struct Epoll {
+    fd: OwnedFd,
+}
+
+impl Epoll {
+    fn new() -> io::Result<Epoll> {
+        let fd = check(unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) })?;
+        Ok(Epoll {
+            fd: unsafe { OwnedFd::from_raw_fd(fd) },
+        })
+    }
+
+    fn control(&self, op: libc::c_int, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        let mut event = libc::epoll_event { events, u64: token };
+        check(unsafe { libc::epoll_ctl(self.fd.as_raw_fd(), op, fd, &mut event) })?;
+        Ok(())
+    }
+
+    fn add(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_ADD, fd, token, events)
+    }
+
+    fn modify(&self, fd: RawFd, token: u64, events: u32) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_MOD, fd, token, events)
+    }
+
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        self.control(libc::EPOLL_CTL_DEL, fd, 0, 0)
+    }
+
+    /// Waits up to `timeout` for events, returning how many were put in `events`.
+    fn wait(&self, events: &mut [libc::epoll_event], timeout: Duration) -> io::Result<usize> {
+        let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        let max = events.len().min(libc::c_int::MAX as usize) as libc::c_int;
+        match check(unsafe {
+            libc::epoll_wait(self.fd.as_raw_fd(), events.as_mut_ptr(), max, timeout)
+        }) {
+            Ok(ready) => Ok(ready as usize),
+            Err(err) if err.kind() == ErrorKind::Interrupted => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// What an event loop shares with the workers and the thread that started it.
+struct Shared {
+    /// An eventfd the loop watches, for waking it up.
+    waker: File,
+    /// Connections the workers are done with, for the loop to watch again.
+    returned: Mutex<Vec<Conn>>,
+    /// Set once the server has drained its connections, to end the loop.
+    exit: AtomicBool,
+}
+
+impl Shared {
+    fn wake(&self) {
+        let _ = (&self.waker).write(&1u64.to_ne_bytes());
+    }
+
+    fn give_back(&self, conn: Conn) {
+        lock(&self.returned).push(conn);
+        self.wake();
+    }
+
+    /// Gives back a connection served in blocking mode since the request it is done with.
+    fn give_back_stream(&self, stream: EspressoStream, tracked: TrackedConnection) {
+        let requests = stream.requests;
+        if let Ok((tcp, input)) = stream.into_connection() {
+            self.give_back(Conn::new(tcp, tracked, input, requests));
+        }
+    }
+}
+
+/// What came of the bytes read from a connection so far.
+enum Next {
+    /// The request isn't complete yet.
+    Read,
+    /// A request was read in full, for a handler to answer. Requests with a body come with their
+    /// route, which was looked up to know how to read the body.
+    Dispatch(Box<EspressoRequest>, Option<RouteMatch>),
+    /// The request is for a route that streams its body, so a worker reads it in blocking mode.
+    HandOff(RouteMatch),
+    /// The request can't be read: the response says why, then the connection is closed.
+    Reject(EspressoResponse),
+    Close,
+}
+
+/// A request whose head has been read, while its body arrives.
+struct Pending {
+    request: Box<EspressoRequest>,
+    found: Option<RouteMatch>,
+    body: PendingBody,
+}
+
+/// How the body of a pending request is read.
+enum PendingBody {
+    None,
+    /// Read in one go once all of it has arrived.
+    Length(usize),
+    /// Decoded as it arrives, into the data read so far.
+    Chunked(ChunkedDecoder, Vec<u8>),
+}
+
+/// Why a connection has waited too long.
+enum Timeout {
+    /// Nothing came between requests: the connection is closed without an answer.
+    Idle,
+    /// The request is taking too long: it is answered with `408 Request Timeout`.
+    Request,
+}
+
+/// A connection and how far its current request has been read or answered.
+struct Conn {
+    tcp: TcpStream,
+    tracked: TrackedConnection,
+    /// What was read and not parsed yet.
+    input: Vec<u8>,
+    /// How much of `input` was searched for the end of the head.
+    scanned: usize,
+    /// The request being read, once its head is complete.
+    pending: Option<Pending>,
+    /// The response being written, and how much of it has been.
+    output: ResponseWriter<Vec<u8>>,
+    written: usize,
+    close_after_write: bool,
+    /// Whether the client has closed its side of the connection.
+    eof: bool,
+    requests: usize,
+    /// When the first byte of the request being read came in.
+    started: Option<Instant>,
+    last_read: Instant,
+    /// The events the loop watches the connection for.
+    interest: u32,
+}
+
+impl Conn {
+    fn new(tcp: TcpStream, tracked: TrackedConnection, input: Vec<u8>, requests: usize) -> Conn {
+        let now = Instant::now();
+        Conn {
+            tcp,
+            tracked,
+            started: (!input.is_empty()).then_some(now),
+            input,
+            scanned: 0,
+            pending: None,
+            output: ResponseWriter::new(Vec::new()),
+            written: 0,
+            close_after_write: false,
+            eof: false,
+            requests,
+            last_read: now,
+            interest: 0,
+        }
+    }
+
+    fn writing(&self) -> bool {
+        !self.output.get_ref().is_empty()
+    }
+
+    /// The most `input` holds while a request is read: a head and a body as large as allowed. A
+    /// chunked body is taken out of `input` as it is decoded, so its framing doesn't add to that.
+    fn max_input(config: &EspressoConfig) -> usize {
+        config
+            .max_request_line
+            .saturating_add(config.max_header_size)
+            .saturating_add(config.max_body_size)
+            .saturating_add(READ_SIZE)
+    }
+
+    /// Reads what has arrived, up to `limit` bytes of input.
+    fn fill(&mut self, limit: usize) -> io::Result<()> {
+        let mut buf = [0; READ_SIZE];
+        while self.input.len() <= limit {
+            match (&self.tcp).read(&mut buf) {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(());
+                }
+                Ok(read) => {
+                    let now = Instant::now();
+                    if self.input.is_empty() && self.pending.is_none() {
+                        self.started = Some(now);
+                        self.tracked.busy();
+                    }
+                    self.input.extend_from_slice(&buf[..read]);
+                    self.last_read = now;
+                    if read < buf.len() {
+                        // Most likely all there is. If not, the loop hears about the rest.
+                        return Ok(());
+                    }
+                }
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks for the empty line ending the head, carrying on from where the last search stopped.
+    fn find_head_end(&mut self) -> Option<usize> {
+        let input = &self.input;
+        // A line ending may have been cut off at the end of what was searched.
+        let start = self.scanned.saturating_sub(2);
+        self.scanned = input.len();
+        (start..input.len())
+            .filter(|&at| input[at] == b'\n')
+            .find_map(|at| match &input[at + 1..] {
+                [b'\n', ..] => Some(at + 2),
+                [b'\r', b'\n', ..] => Some(at + 3),
+                _ => None,
+            })
+    }
+
+    /// Parses the request at the start of `input` as it arrives: its head once it is complete,
+    /// then its body.
+    fn parse(&mut self, internal: &EspressoInternal) -> Next {
+        if self.pending.is_none() {
+            if let Some(next) = self.parse_head(internal) {
+                return next;
+            }
+        }
+        self.read_body(&internal.config)
+    }
+
+    /// Parses the head at the start of `input` once it has all arrived into `pending`, taking it
+    /// out of `input`. Returns what comes next instead if the head isn't there yet, can't be
+    /// read, or is for a route that reads the body itself.
+    fn parse_head(&mut self, internal: &EspressoInternal) -> Option<Next> {
+        let config = &internal.config;
+        // Empty lines before a request line are skipped (RFC 9112 §2.2).
+        let blank = self
+            .input
+            .iter()
+            .take_while(|&&byte| byte == b'\r' || byte == b'\n')
+            .count();
+        if blank > 0 {
+            self.input.drain(..blank);
+            self.scanned = 0;
+        }
+        if self.input.is_empty() {
+            self.started = None;
+            return Some(if self.eof { Next::Close } else { Next::Read });
+        }
+
+        let head_end = match self.find_head_end() {
+            Some(head_end) => head_end,
+            None if self.eof
+                || self.input.len() > config.max_request_line + config.max_header_size =>
+            {
+                // The head is cut short or too large: the parser tells which.
+                let parsed = parser::parse_head(
+                    &mut self.input.as_slice(),
+                    config.max_request_line,
+                    config.max_header_size,
+                );
+                return Some(match parsed {
+                    Err(err) => Next::Reject(error_response(err)),
+                    Ok(_) => Next::Close,
+                });
+            }
+            None => return Some(Next::Read),
+        };
+
+        let parsed = parser::parse_head(
+            &mut &self.input[..head_end],
+            config.max_request_line,
+            config.max_header_size,
+        );
+        let mut head = match parsed {
+            Ok(Some(head)) => head,
+            Ok(None) => return Some(Next::Close),
+            Err(err) => return Some(Next::Reject(error_response(err))),
+        };
+        request::forward_peer(&mut head.headers, &self.tcp);
+        let framing = match parser::body_framing(&head.headers) {
+            Ok(framing) => framing,
+            Err(err) => return Some(Next::Reject(error_response(err))),
+        };
+        let request = EspressoRequest::from_parts(head, None);
+        let mut found = match framing {
+            BodyFraming::None => None,
+            _ => Some(internal.router.lookup(&request)),
+        };
+        if let Some(found) = found.take_if(|found| found.streams_body()) {
+            return Some(Next::HandOff(found));
+        }
+
+        self.input.drain(..head_end);
+        self.scanned = 0;
+        let body = match framing {
+            BodyFraming::None => PendingBody::None,
+            BodyFraming::Length(len) => PendingBody::Length(len),
+            BodyFraming::Chunked => PendingBody::Chunked(ChunkedDecoder::new(config), Vec::new()),
+        };
+        self.pending = Some(Pending {
+            request: Box::new(request),
+            found,
+            body,
+        });
+        None
+    }
+
+    /// Reads as much of the body of the pending request as has arrived, and hands the request on
+    /// once all of it has.
+    fn read_body(&mut self, config: &EspressoConfig) -> Next {
+        let Some(mut pending) = self.pending.take() else {
+            return Next::Read;
+        };
+        let body = match &mut pending.body {
+            PendingBody::None => None,
+            PendingBody::Length(len) => {
+                let len = *len;
+                if len <= config.max_body_size && self.input.len() < len && !self.eof {
+                    self.pending = Some(pending);
+                    return Next::Read;
+                }
+                let framing = BodyFraming::Length(len);
+                match parser::read_body(&mut self.input.as_slice(), framing, config) {
+                    Ok(read) => {
+                        self.input.drain(..len);
+                        read
+                    }
+                    Err(err) => return Next::Reject(error_response(err)),
+                }
+            }
+            PendingBody::Chunked(decoder, data) => {
+                match decoder.decode(&self.input, data, config.max_body_size) {
+                    Ok(used) => {
+                        self.input.drain(..used);
+                    }
+                    Err(err) => return Next::Reject(error_response(err)),
+                }
+                if !decoder.is_done() {
+                    if self.eof {
+                        return Next::Reject(error_response(
+                            EspressoRequestError::IncompleteRequest(
+                                "Connection closed before the end of the body.".to_string(),
+                            ),
+                        ));
+                    }
+                    // What is left is a chunk header or trailer section too long to decode.
+                    if self.input.len() > Conn::max_input(config) {
+                        return Next::Reject(error_response(EspressoRequestError::PayloadTooLarge));
+                    }
+                    self.pending = Some(pending);
+                    return Next::Read;
+                }
+                Some(RequestBody {
+                    data: mem::take(data),
+                    extensions: mem::take(&mut decoder.extensions),
+                    trailers: mem::take(&mut decoder.trailers),
+                })
+            }
+        };
+
+        if self.input.is_empty() && self.input.capacity() > KEPT_CAPACITY {
+            self.input = Vec::new();
+        }
+        self.started = (!self.input.is_empty()).then(Instant::now);
+        self.requests += 1;
+        pending.request.set_body(body);
+        Next::Dispatch(pending.request, pending.found)
+    }
+
+    /// Queues `response` for writing.
+    fn queue(&mut self, response: EspressoResponse) {
+        self.close_after_write = response.headers.contains_token("Connection", "close");
+        // Only byte bodies are queued, and writing those to a buffer can't fail.
+        let _ = self.output.write_response(response);
+    }
+
+    /// Writes as much of the queued response as the connection takes. Returns whether all of it
+    /// was.
+    fn flush(&mut self) -> io::Result<bool> {
+        let output = self.output.get_mut();
+        while self.written < output.len() {
+            match (&self.tcp).write(&output[self.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(written) => self.written += written,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if output.capacity() > KEPT_CAPACITY {
+            *output = Vec::new();
+        } else {
+            output.clear();
+        }
+        self.written = 0;
+        Ok(true)
+    }
+
+    fn expired(&self, config: &EspressoConfig, now: Instant) -> Option<Timeout> {
+        if self.writing() {
+            return None;
+        }
+        let idle = config
+            .idle_timeout
+            .is_some_and(|timeout| now.duration_since(self.last_read) >= timeout);
+        match self.started {
+            None => idle.then_some(Timeout::Idle),
+            Some(started) => {
+                let head_late = self.pending.is_none()
+                    && config
+                        .header_timeout
+                        .is_some_and(|timeout| now.duration_since(started) >= timeout);
+                (idle || head_late).then_some(Timeout::Request)
+            }
+        }
+    }
+
+    /// Switches to blocking mode, for serving the connection as the threaded engine does.
+    fn into_stream(self, config: &EspressoConfig) -> Option<(EspressoStream, TrackedConnection)> {
+        let stream =
+            EspressoStream::resume(self.tcp, self.input, config.clone(), self.requests).ok()?;
+        Some((stream, self.tracked))
+    }
+}
+
+struct EventLoop {
+    internal: Arc<EspressoInternal>,
+    pool: Arc<dyn Executor>,
+    listener: TcpListener,
+    listening: bool,
+    epoll: Epoll,
+    shared: Arc<Shared>,
+    conns: HashMap<u64, Conn>,
+    next_token: u64,
+}
+
+impl EventLoop {
+    fn new(
+        internal: Arc<EspressoInternal>,
+        listener: TcpListener,
+        pool: Arc<dyn Executor>,
+    ) -> io::Result<EventLoop> {
+        let epoll = Epoll::new()?;
+        let waker = check(unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) })?;
+        let waker = unsafe { File::from_raw_fd(waker) };
+        // Each connection wakes up a single loop, which accepts it.
+        let exclusive = libc::EPOLLEXCLUSIVE as u32;
+        epoll.add(listener.as_raw_fd(), LISTENER, READABLE | exclusive)?;
+        epoll.add(waker.as_raw_fd(), WAKER, READABLE)?;
+        Ok(EventLoop {
+            internal,
+            pool,
+            listener,
+            listening: true,
+            epoll,
+            shared: Arc::new(Shared {
+                waker,
+                returned: Mutex::new(Vec::new()),
+                exit: AtomicBool::new(false),
+            }),
+            conns: HashMap::new(),
+            next_token: WAKER + 1,
+        })
+    }
+
+    fn run(mut self) {
+        let mut events = vec![libc::epoll_event { events: 0, u64: 0 }; 1024];
+        let mut next_sweep = Instant::now() + SWEEP_INTERVAL;
+        while !self.shared.exit.load(Ordering::SeqCst) {
+            let ready = match self.epoll.wait(&mut events, SWEEP_INTERVAL) {
+                Ok(ready) => ready,
+                Err(err) => {
+                    eprintln!("Error while waiting for events, stopping an event loop: {err}");
+                    return;
+                }
+            };
+            for event in &events[..ready] {
+                match event.u64 {
+                    LISTENER => self.accept(),
+                    WAKER => self.take_back(),
+                    token => self.ready(token),
+                }
+            }
+            if self.listening && self.internal.shutdown.is_stopping() {
+                let _ = self.epoll.delete(self.listener.as_raw_fd());
+                self.listening = false;
+            }
+            let now = Instant::now();
+            if now >= next_sweep {
+                self.sweep(now);
+                next_sweep = now + SWEEP_INTERVAL;
+            }
+        }
+    }
+
+    fn accept(&mut self) {
+        loop {
+            let tcp = match self.listener.accept() {
+                Ok((tcp, _)) => tcp,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => return,
+            };
+            // Connections coming in while shutting down, such as the one waking the accept loop
+            // of the threaded engine, are turned away.
+            if self.internal.shutdown.is_stopping() || tcp.set_nonblocking(true).is_err() {
+                continue;
+            }
+            if let Some(tracked) = self.internal.shutdown.track(&tcp) {
+                self.watch(Conn::new(tcp, tracked, Vec::new(), 0));
+            }
+        }
+    }
+
+    /// Watches the connections the workers gave back.
+    fn take_back(&mut self) {
+        let mut counter = [0; 8];
+        let _ = (&self.shared.waker).read(&mut counter);
+        let returned = mem::take(&mut *lock(&self.shared.returned));
+        for conn in returned {
+            self.watch(conn);
+        }
+    }
+
+    fn watch(&mut self, mut conn: Conn) {
+        let token = self.next_token;
+        self.next_token += 1;
+        let writing = conn.writing();
+        conn.interest = if writing { WRITABLE } else { READABLE };
+        if self
+            .epoll
+            .add(conn.tcp.as_raw_fd(), token, conn.interest)
+            .is_err()
+        {
+            return;
+        }
+        self.conns.insert(token, conn);
+        if !writing {
+            self.next_request(token);
+        }
+    }
+
+    /// Stops watching a connection, which the caller closes or hands to a worker.
+    fn unwatch(&mut self, token: u64) -> Option<Conn> {
+        let conn = self.conns.remove(&token)?;
+        // Workers and the shutdown state hold duplicates of the socket, so closing it wouldn't
+        // take it out of the epoll set.
+        let _ = self.epoll.delete(conn.tcp.as_raw_fd());
+        Some(conn)
+    }
+
+    fn close(&mut self, token: u64) {
+        self.unwatch(token);
+    }
+
+    fn set_interest(&mut self, token: u64, interest: u32) {
+        let Some(conn) = self.conns.get_mut(&token) else {
+            return;
+        };
+        if conn.interest != interest {
+            if self
+                .epoll
+                .modify(conn.tcp.as_raw_fd(), token, interest)
+                .is_err()
+            {
+                return self.close(token);
+            }
+            conn.interest = interest;
+        }
+    }
+
+    fn ready(&mut self, token: u64) {
+        let limit = Conn::max_input(&self.internal.config);
+        let Some(conn) = self.conns.get_mut(&token) else {
+            return;
+        };
+        if conn.writing() {
+            return self.send(token);
+        }
+        match conn.fill(limit) {
+            Ok(()) => self.advance(token),
+            Err(_) => self.close(token),
+        }
+    }
+
+    /// Starts on the next request of a connection, some of which may have arrived already.
+    fn next_request(&mut self, token: u64) {
+        let Some(conn) = self.conns.get_mut(&token) else {
+            return;
+        };
+        conn.last_read = Instant::now();
+        if conn.input.is_empty() {
+            // Shutting down closes connections between requests. One accepted before still gets
+            // its first request answered.
+            if conn.eof || (conn.requests > 0 && !conn.tracked.idle()) {
+                self.close(token);
+            }
+            return;
+        }
+        self.advance(token);
+    }
+
+    /// Parses what was read from a connection and acts on it.
+    fn advance(&mut self, token: u64) {
+        let Some(conn) = self.conns.get_mut(&token) else {
+            return;
+        };
+        match conn.parse(&self.internal) {
+            Next::Read => {}
+            Next::Dispatch(request, found) => {
+                if let Some(conn) = self.unwatch(token) {
+                    self.dispatch(conn, *request, found);
+                }
+            }
+            Next::HandOff(found) => {
+                if let Some(conn) = self.unwatch(token) {
+                    self.hand_off(conn, found);
+                }
+            }
+            Next::Reject(response) => {
+                conn.queue(response);
+                self.send(token);
+            }
+            Next::Close => self.close(token),
+        }
+    }
+
+    /// Writes what the connection takes of its response, and watches it for the rest.
+    fn send(&mut self, token: u64) {
+        let Some(conn) = self.conns.get_mut(&token) else {
+            return;
+        };
+        match conn.flush() {
+            Ok(true) if conn.close_after_write => self.close(token),
+            Ok(true) => {
+                self.set_interest(token, READABLE);
+                self.next_request(token);
+            }
+            Ok(false) => self.set_interest(token, WRITABLE),
+            Err(_) => self.close(token),
+        }
+    }
+
+    /// Has a worker answer `request`. The connection comes back once the response is written or
+    /// queued.
+    fn dispatch(&self, mut conn: Conn, mut request: EspressoRequest, found: Option<RouteMatch>) {
+        let internal = Arc::clone(&self.internal);
+        let shared = Arc::clone(&self.shared);
+        self.pool.execute(Box::new(move || {
+            let keep_alive = internal
+                .config
+                .max_requests_per_connection
+                .is_none_or(|max| conn.requests < max);
+            let response = internal.dispatch(&mut request, found, keep_alive);
+            let buffered = response.upgrade.is_none()
+                && !response.long_lived
+                && matches!(response.body, Body::Bytes(_));
+            if !buffered {
+                // Produced and written as the threaded engine does, however long that takes.
+                let Some((stream, tracked)) = conn.into_stream(&internal.config) else {
+                    return;
+                };
+                if let Some((stream, tracked)) =
+                    Arc::clone(&internal).answer(stream, request, response, tracked)
+                {
+                    shared.give_back_stream(stream, tracked);
+                }
+                return;
+            }
+            conn.queue(response);
+            // Most responses fit in the socket buffer, which saves the loop a round.
+            match conn.flush() {
+                Ok(true) if conn.close_after_write => {}
+                Ok(_) => shared.give_back(conn),
+                Err(_) => {}
+            }
+        }));
+    }
+
+    /// Has a worker read the request, whose body its handler streams, in blocking mode.
+    fn hand_off(&self, conn: Conn, found: RouteMatch) {
+        let internal = Arc::clone(&self.internal);
+        let shared = Arc::clone(&self.shared);
+        self.pool.execute(Box::new(move || {
+            let Some((stream, tracked)) = conn.into_stream(&internal.config) else {
+                return;
+            };
+            if let Some((stream, tracked)) =
+                Arc::clone(&internal).serve_one(stream, tracked, Some(found))
+            {
+                shared.give_back_stream(stream, tracked);
+            }
+        }));
+    }
+
+    /// Closes the connections that waited too long, answering the ones in the middle of a
+    /// request with `408 Request Timeout`.
+    fn sweep(&mut self, now: Instant) {
+        let config = &self.internal.config;
+        let expired: Vec<_> = self
+            .conns
+            .iter()
+            .filter_map(|(&token, conn)| Some((token, conn.expired(config, now)?)))
+            .collect();
+        for (token, timeout) in expired {
+            match timeout {
+                Timeout::Idle => self.close(token),
+                Timeout::Request => {
+                    if let Some(conn) = self.conns.get_mut(&token) {
+                        conn.queue(error_response(EspressoRequestError::Timeout));
+                        self.send(token);
+                    }
+                }
+            }
+        }
+    }
+}
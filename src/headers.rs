@@ -0,0 +1,135 @@
+/// The header fields of a request or response.
+///
+/// Names are compared case-insensitively but keep the casing they were added with, which is how
+/// they are written out. A name can have several values, each kept as its own field in the order
+/// they were added, so fields such as `Set-Cookie` that can't be combined into one list survive.
+/// Values are stored without surrounding whitespace.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    fields: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of `name` joined into one comma-separated list, as RFC 9110 §5.3 allows for
+    /// fields defined as lists. `None` if the field isn't present.
+    pub fn get_joined(&self, name: &str) -> Option<String> {
+        let values: Vec<&str> = self.get_all(name).collect();
+        if values.is_empty() {
+            return None;
+        }
+        Some(values.join(", "))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Whether `token` is in the comma-separated list of values of `name`, such as `close` in
+    /// `Connection: keep-alive, close`. Tokens are compared case-insensitively.
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|item| item.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Sets `name` to `value`, replacing every value it had.
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.remove(name);
+        self.append(name, value);
+    }
+
+    /// Adds `value` to the values of `name`.
+    pub fn append(&mut self, name: &str, value: &str) {
+        self.fields.push((
+            name.to_string(),
+            value.trim_matches([' ', '\t']).to_string(),
+        ));
+    }
+
+    /// Removes every value of `name`, returning whether there were any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.fields.len();
+        self.fields
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.fields.len() != len
+    }
+
+    /// Every field, with the casing it was added with and in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    /// The number of fields, counting each value separately.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// The `Content-Type` header.
+    pub fn content_type(&self) -> Option<&str> {
+        self.get("Content-Type")
+    }
+
+    /// The `Content-Length` header as a number. `None` if it is missing or isn't a valid length.
+    pub fn content_length(&self) -> Option<u64> {
+        let value = self.get("Content-Length")?;
+        if value.is_empty() || !value.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        value.parse().ok()
+    }
+
+    /// The media ranges of every `Accept` header, in order, with their parameters such as `q`.
+    pub fn accept(&self) -> Vec<&str> {
+        self.get_all("Accept")
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|range| !range.is_empty())
+            .collect()
+    }
+
+    /// The `Authorization` header.
+    pub fn authorization(&self) -> Option<&str> {
+        self.get("Authorization")
+    }
+
+    /// The `Host` header.
+    pub fn host(&self) -> Option<&str> {
+        self.get("Host")
+    }
+}
+
+impl<'a> FromIterator<(&'a str, &'a str)> for HeaderMap {
+    fn from_iter<I: IntoIterator<Item = (&'a str, &'a str)>>(iter: I) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in iter {
+            headers.append(name, value);
+        }
+        headers
+    }
+}
@@ -1,5 +1,20 @@
+pub mod body;
+pub mod builder;
+pub mod config;
 pub mod error;
 pub mod espresso;
+#[cfg(target_os = "linux")]
+mod event_loop;
+pub mod headers;
+pub mod middleware;
+mod parser;
 pub mod request;
 pub mod response;
+pub mod route;
+pub mod router;
+pub mod shutdown;
+pub mod sse;
+pub mod status;
 pub mod threads;
+pub mod url;
+pub mod websocket;
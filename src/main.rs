@@ -6,7 +6,10 @@ fn main() {
         res.send("Hello world!");
         res.send(&format!(
             "I saw {} in my dreams",
-            req.body.clone().unwrap_or("Nothing!".to_string())
+            match req.body_text() {
+                Ok("") | Err(_) => "Nothing!",
+                Ok(text) => text,
+            }
         ));
     });
     app.all(
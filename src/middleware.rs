@@ -0,0 +1,100 @@
+use std::sync::Arc;
+
+use crate::{
+    error::{HandlerResult, HttpError},
+    request::EspressoRequest,
+    response::EspressoResponse,
+};
+
+/// A function that runs before the handler of a request. It decides whether the request goes any
+/// further by calling [`Next::run`], and may keep working on the response after it returns.
+///
+/// Returning an error skips the rest of the chain and hands the error to the error middleware.
+pub type EspressoMiddleware = Box<
+    dyn Fn(&mut EspressoRequest, &mut EspressoResponse, Next<'_>) -> Result<(), HttpError>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// The Express four-argument form of middleware: receives the error raised by a handler or
+/// middleware. Returning `Ok` means the error was dealt with in the response; returning an error,
+/// either the same one or another, passes it on to the next error middleware.
+pub type EspressoErrorMiddleware = Box<
+    dyn Fn(HttpError, &EspressoRequest, &mut EspressoResponse) -> Result<(), HttpError>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// Boxes a middleware function, for use with [`crate::router::Router::route_with`].
+pub fn from_fn<R: HandlerResult>(
+    middleware: impl Fn(&mut EspressoRequest, &mut EspressoResponse, Next) -> R + Send + Sync + 'static,
+) -> EspressoMiddleware {
+    Box::new(move |request, response, next| middleware(request, response, next).into_result())
+}
+
+/// Boxes an error middleware function.
+pub fn error_from_fn<R: HandlerResult>(
+    middleware: impl Fn(HttpError, &EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+) -> EspressoErrorMiddleware {
+    Box::new(move |err, request, response| middleware(err, request, response).into_result())
+}
+
+/// The rest of the middleware chain of a request, ending with its handler.
+pub struct Next<'a> {
+    stack: &'a [Arc<EspressoMiddleware>],
+    endpoint: &'a dyn Fn(&mut EspressoRequest, &mut EspressoResponse) -> Result<(), HttpError>,
+}
+
+impl<'a> Next<'a> {
+    pub(crate) fn new(
+        stack: &'a [Arc<EspressoMiddleware>],
+        endpoint: &'a dyn Fn(&mut EspressoRequest, &mut EspressoResponse) -> Result<(), HttpError>,
+    ) -> Next<'a> {
+        Next { stack, endpoint }
+    }
+
+    /// Passes the request on to the next middleware, or to the handler once every middleware has run.
+    /// Returns when everything downstream is done with the request, with the error raised
+    /// downstream if there was one. Returning that error lets it reach the error middleware.
+    pub fn run(
+        self,
+        request: &mut EspressoRequest,
+        response: &mut EspressoResponse,
+    ) -> Result<(), HttpError> {
+        match self.stack.split_first() {
+            Some((middleware, stack)) => middleware(
+                request,
+                response,
+                Next {
+                    stack,
+                    endpoint: self.endpoint,
+                },
+            ),
+            None => (self.endpoint)(request, response),
+        }
+    }
+}
+
+/// Answers an error no error middleware dealt with: the response is replaced by one with the
+/// status of the error. Server errors are logged and their message is kept out of the response.
+pub(crate) fn default_error_handler(
+    err: HttpError,
+    request: &EspressoRequest,
+    response: &mut EspressoResponse,
+) {
+    *response = EspressoResponse::new();
+    response.set_status(err.status);
+    if response.status.is_server_error() {
+        eprintln!(
+            "Error while handling {} {} (route {}): {err}",
+            request.method.as_str(),
+            request.resource,
+            request.route().unwrap_or("none")
+        );
+        response.send(&response.message.clone());
+    } else {
+        response.send(&err.message);
+    }
+}
@@ -0,0 +1,628 @@
+//! Parsing of the request line and header section of an HTTP/1.1 request (RFC 9112).
+
+use std::io::{self, BufRead, ErrorKind};
+
+use crate::{
+    config::EspressoConfig, error::EspressoRequestError, headers::HeaderMap,
+    request::RequestMethod, url,
+};
+
+/// Longest request line accepted by default, including the line ending.
+pub(crate) const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// Largest header section accepted by default, including line endings and the empty line that
+/// ends it.
+pub(crate) const MAX_HEADER_SECTION: usize = 64 * 1024;
+/// Empty lines tolerated before a request line, such as the stray CRLF some clients send after a body.
+const MAX_EMPTY_LINES: usize = 4;
+
+/// The request line and headers of a request, before its body is read.
+pub(crate) struct RequestHead {
+    pub method: RequestMethod,
+    pub target: String,
+    /// The path of the target, percent-decoded and normalized.
+    pub path: String,
+    /// The query string of the target, without the `?`.
+    pub query: Option<String>,
+    pub version: String,
+    pub headers: HeaderMap,
+}
+
+/// Reads one request head from `reader`, with a request line of at most `max_request_line` bytes
+/// and a header section of at most `max_header_section`.
+///
+/// Returns `Ok(None)` if the connection was closed before the request started, which is how a
+/// client ends a persistent connection. Leading empty lines are skipped, as allowed by RFC 9112 §2.2.
+pub(crate) fn parse_head<R: BufRead>(
+    reader: &mut R,
+    max_request_line: usize,
+    max_header_section: usize,
+) -> Result<Option<RequestHead>, EspressoRequestError> {
+    let mut empty_lines = 0;
+    let request_line = loop {
+        match read_line(reader, max_request_line)? {
+            None => return Ok(None),
+            Some(line) if line.is_empty() && empty_lines < MAX_EMPTY_LINES => empty_lines += 1,
+            Some(line) if line.is_empty() => {
+                return Err(EspressoRequestError::MalformedRequest(
+                    "Too many empty lines before the request line.".to_string(),
+                ))
+            }
+            Some(line) => break line,
+        }
+    };
+    let (method, target, version) = parse_request_line(&request_line)?;
+    let (path, query) = match method {
+        // CONNECT names the host and port to tunnel to instead of a path (RFC 9112 §3.2.3).
+        RequestMethod::CONNECT => (target.clone(), None),
+        _ => url::split_target(&target)?,
+    };
+
+    let headers = read_fields(reader, max_header_section)?;
+
+    if version == "HTTP/1.1" && !headers.contains("Host") {
+        return Err(EspressoRequestError::MalformedRequest(
+            "HTTP/1.1 requests must have a Host header.".to_string(),
+        ));
+    }
+    if headers.get_all("Host").count() > 1 || headers.host().is_some_and(|host| host.contains(','))
+    {
+        return Err(EspressoRequestError::MalformedRequest(
+            "Request has more than one Host header.".to_string(),
+        ));
+    }
+
+    Ok(Some(RequestHead {
+        method,
+        target,
+        path,
+        query,
+        version,
+        headers,
+    }))
+}
+
+/// Reads a header section or the trailer section of a chunked body, up to and including the
+/// empty line that ends it, which can't take more than `limit` bytes.
+fn read_fields<R: BufRead>(
+    reader: &mut R,
+    limit: usize,
+) -> Result<HeaderMap, EspressoRequestError> {
+    let mut fields = HeaderMap::new();
+    let mut remaining = limit;
+    loop {
+        let line = match read_line(reader, remaining) {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                return Err(EspressoRequestError::IncompleteRequest(
+                    "Connection closed before the end of the headers.".to_string(),
+                ))
+            }
+            Err(EspressoRequestError::UriTooLong) => {
+                return Err(EspressoRequestError::HeaderTooLarge)
+            }
+            Err(err) => return Err(err),
+        };
+        // Line endings count towards the limit too, so a flood of empty header lines can't go on forever.
+        remaining = remaining.saturating_sub(line.len() + 2);
+        if line.is_empty() {
+            return Ok(fields);
+        }
+        let (name, value) = parse_header_field(&line)?;
+        fields.append(&name, &value);
+    }
+}
+
+/// A request body read in full, along with what the chunked encoding carried besides the data.
+pub(crate) struct RequestBody {
+    pub data: Vec<u8>,
+    pub extensions: Vec<(String, String)>,
+    pub trailers: HeaderMap,
+}
+
+/// How the length of a request body is determined (RFC 9112 §6.3).
+#[derive(Clone, Copy)]
+pub(crate) enum BodyFraming {
+    None,
+    Length(usize),
+    Chunked,
+}
+
+/// Works out from the headers how the body of a request is framed.
+///
+/// Requests with both Content-Length and Transfer-Encoding are rejected rather than picking one:
+/// a proxy in front of the server could pick the other, and smuggle a request past it.
+pub(crate) fn body_framing(headers: &HeaderMap) -> Result<BodyFraming, EspressoRequestError> {
+    let Some(encoding) = headers.get_joined("Transfer-Encoding") else {
+        return Ok(match content_length(headers)? {
+            Some(len) => BodyFraming::Length(len),
+            None => BodyFraming::None,
+        });
+    };
+    if headers.contains("Content-Length") {
+        return Err(EspressoRequestError::MalformedRequest(
+            "Request has both Content-Length and Transfer-Encoding.".to_string(),
+        ));
+    }
+
+    let codings: Vec<&str> = encoding.split(',').map(str::trim).collect();
+    match codings.as_slice() {
+        [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(BodyFraming::Chunked),
+        [.., last] if last.eq_ignore_ascii_case("chunked") => Err(
+            EspressoRequestError::UnsupportedTransferEncoding(encoding.clone()),
+        ),
+        // Without chunked last, the length of a request body can't be known.
+        _ => Err(EspressoRequestError::MalformedRequest(
+            "Transfer-Encoding of a request must end with chunked.".to_string(),
+        )),
+    }
+}
+
+/// Reads the body of a request, framed by Content-Length or by the chunked transfer coding.
+///
+/// Returns `Ok(None)` if the request has no body. Only the body is consumed, so the reader is left
+/// at the start of the next request. The body and the framing of a chunked one are held to the
+/// limits of `config`.
+pub(crate) fn read_body<R: BufRead>(
+    reader: &mut R,
+    framing: BodyFraming,
+    config: &EspressoConfig,
+) -> Result<Option<RequestBody>, EspressoRequestError> {
+    let len = match framing {
+        BodyFraming::None => return Ok(None),
+        BodyFraming::Length(len) => len,
+        BodyFraming::Chunked => return read_chunked_body(reader, config).map(Some),
+    };
+    if len > config.max_body_size {
+        return Err(EspressoRequestError::PayloadTooLarge);
+    }
+
+    let mut data: Vec<u8> = vec![0; len];
+    match reader.read_exact(&mut data) {
+        Ok(()) => Ok(Some(RequestBody {
+            data,
+            extensions: Vec::new(),
+            trailers: HeaderMap::new(),
+        })),
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => {
+            Err(EspressoRequestError::IncompleteRequest(
+                "Connection closed before the end of the body.".to_string(),
+            ))
+        }
+        Err(err) => Err(read_error(err, "Couldn't read request body")),
+    }
+}
+
+fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    config: &EspressoConfig,
+) -> Result<RequestBody, EspressoRequestError> {
+    let mut chunked = ChunkedDecoder::new(config);
+    let mut data: Vec<u8> = Vec::new();
+    let mut buf = [0; 8 * 1024];
+    loop {
+        let read = chunked.read(reader, &mut buf)?;
+        if read == 0 {
+            break;
+        }
+        if data.len() + read > config.max_body_size {
+            return Err(EspressoRequestError::PayloadTooLarge);
+        }
+        data.extend_from_slice(&buf[..read]);
+    }
+    Ok(RequestBody {
+        data,
+        extensions: chunked.extensions,
+        trailers: chunked.trailers,
+    })
+}
+
+/// Decodes a body sent with `Transfer-Encoding: chunked` (RFC 9112 §7.1), reading no further than
+/// the end of the body.
+pub(crate) struct ChunkedDecoder {
+    /// Data bytes left in the current chunk.
+    remaining: usize,
+    done: bool,
+    /// Longest chunk header, including the line ending.
+    max_line: usize,
+    /// Largest trailer section, including line endings and the empty line that ends it.
+    max_trailers: usize,
+    /// Bytes of chunk extensions still allowed, so a client can't make the server keep an
+    /// unbounded number of them with chunks of a byte or two.
+    extensions_allowed: usize,
+    /// The extensions of every chunk, in order. Extensions without a value have an empty one.
+    pub extensions: Vec<(String, String)>,
+    /// Fields of the trailer section. Filled in once the body is read.
+    pub trailers: HeaderMap,
+}
+
+impl ChunkedDecoder {
+    /// A decoder holding chunk headers to the request line limit of `config`, the trailer section
+    /// to its header section limit, and chunk extensions to its own limit for them.
+    pub fn new(config: &EspressoConfig) -> ChunkedDecoder {
+        ChunkedDecoder {
+            remaining: 0,
+            done: false,
+            max_line: config.max_request_line,
+            max_trailers: config.max_header_size,
+            extensions_allowed: config.max_chunk_extensions,
+            extensions: Vec::new(),
+            trailers: HeaderMap::new(),
+        }
+    }
+
+    /// Whether the last chunk and the trailer section have been read.
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Reads decoded body data from `reader` into `buf`, returning how much was read. Returns 0 once
+    /// the body ends.
+    pub fn read<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        buf: &mut [u8],
+    ) -> Result<usize, EspressoRequestError> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.remaining == 0 {
+            if self.done {
+                return Ok(0);
+            }
+            self.read_chunk_header(reader)?;
+        }
+
+        let available = loop {
+            match reader.fill_buf() {
+                Ok(available) => break available,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(read_error(err, "Couldn't read request body")),
+            }
+        };
+        if available.is_empty() {
+            return Err(EspressoRequestError::IncompleteRequest(
+                "Connection closed before the end of the body.".to_string(),
+            ));
+        }
+        let read = available.len().min(buf.len()).min(self.remaining);
+        buf[..read].copy_from_slice(&available[..read]);
+        reader.consume(read);
+        self.remaining -= read;
+
+        if self.remaining == 0 {
+            // Every chunk's data is followed by a line ending.
+            match read_line(reader, 2) {
+                Ok(Some(line)) if line.is_empty() => {}
+                Ok(None) => {
+                    return Err(EspressoRequestError::IncompleteRequest(
+                        "Connection closed before the end of the body.".to_string(),
+                    ))
+                }
+                Err(err @ EspressoRequestError::IncompleteRequest(_)) => return Err(err),
+                _ => {
+                    return Err(EspressoRequestError::MalformedRequest(
+                        "Chunk data is longer than its size.".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(read)
+    }
+
+    /// Decodes as much of `input` as has arrived in full, adding the data to `data`, and returns
+    /// how many bytes of `input` it used. What is left is cut short in the middle of a chunk
+    /// header, a line ending or the trailer section, and is decoded again once the rest arrives.
+    ///
+    /// Fails with `PayloadTooLarge` once `data` would hold more than `max_body_size` bytes.
+    pub fn decode(
+        &mut self,
+        input: &[u8],
+        data: &mut Vec<u8>,
+        max_body_size: usize,
+    ) -> Result<usize, EspressoRequestError> {
+        let mut rest = input;
+        let mut buf = [0; 8 * 1024];
+        while !self.done {
+            let (before, remaining, allowed) = (rest, self.remaining, self.extensions_allowed);
+            let extensions = self.extensions.len();
+            match self.read(&mut rest, &mut buf) {
+                Ok(read) => {
+                    if data.len() + read > max_body_size {
+                        return Err(EspressoRequestError::PayloadTooLarge);
+                    }
+                    data.extend_from_slice(&buf[..read]);
+                }
+                Err(EspressoRequestError::IncompleteRequest(_)) => {
+                    // Undone, so the step starts over with the rest of its input.
+                    rest = before;
+                    self.remaining = remaining;
+                    self.extensions_allowed = allowed;
+                    self.extensions.truncate(extensions);
+                    break;
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(input.len() - rest.len())
+    }
+
+    /// Reads `chunk-size [ chunk-ext ] CRLF`, and the trailer section after the last chunk.
+    fn read_chunk_header<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+    ) -> Result<(), EspressoRequestError> {
+        let line = match read_line(reader, self.max_line) {
+            Ok(Some(line)) => line,
+            Ok(None) => {
+                return Err(EspressoRequestError::IncompleteRequest(
+                    "Connection closed before the end of the body.".to_string(),
+                ))
+            }
+            Err(EspressoRequestError::UriTooLong) => {
+                return Err(EspressoRequestError::MalformedRequest(
+                    "Chunk header too long.".to_string(),
+                ))
+            }
+            Err(err) => return Err(err),
+        };
+
+        let digits = line
+            .iter()
+            .take_while(|byte| byte.is_ascii_hexdigit())
+            .count();
+        let size = std::str::from_utf8(&line[..digits])
+            .ok()
+            .and_then(|digits| usize::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| {
+                EspressoRequestError::MalformedRequest("Invalid chunk size.".to_string())
+            })?;
+        let extensions = &line[digits..];
+        self.extensions_allowed = self
+            .extensions_allowed
+            .checked_sub(extensions.len())
+            .ok_or(EspressoRequestError::PayloadTooLarge)?;
+        parse_chunk_extensions(extensions, &mut self.extensions)?;
+
+        if size == 0 {
+            self.trailers = read_fields(reader, self.max_trailers)?;
+            self.done = true;
+        }
+        self.remaining = size;
+        Ok(())
+    }
+}
+
+/// Parses `*( BWS ";" BWS ext-name [ BWS "=" BWS ext-val ] ) BWS`, where `ext-val` is a token or
+/// a quoted string.
+fn parse_chunk_extensions(
+    line: &[u8],
+    extensions: &mut Vec<(String, String)>,
+) -> Result<(), EspressoRequestError> {
+    let malformed =
+        || EspressoRequestError::MalformedRequest("Invalid chunk extension.".to_string());
+    let is_whitespace = |byte: &u8| *byte == b' ' || *byte == b'\t';
+    let take_token = |rest: &[u8]| rest.iter().take_while(|&&byte| is_tchar(byte)).count();
+
+    let mut rest = line;
+    loop {
+        rest = &rest[rest.iter().take_while(|byte| is_whitespace(byte)).count()..];
+        let Some((&b';', after)) = rest.split_first() else {
+            return if rest.is_empty() {
+                Ok(())
+            } else {
+                Err(malformed())
+            };
+        };
+        rest = &after[after.iter().take_while(|byte| is_whitespace(byte)).count()..];
+
+        let name_len = take_token(rest);
+        if name_len == 0 {
+            return Err(malformed());
+        }
+        let name = String::from_utf8_lossy(&rest[..name_len]).to_string();
+        rest = &rest[name_len..];
+
+        let mut value = String::new();
+        let after_space = &rest[rest.iter().take_while(|byte| is_whitespace(byte)).count()..];
+        if let Some((&b'=', after)) = after_space.split_first() {
+            rest = &after[after.iter().take_while(|byte| is_whitespace(byte)).count()..];
+            if let Some((&b'"', quoted)) = rest.split_first() {
+                let mut bytes = quoted.iter();
+                let mut raw: Vec<u8> = Vec::new();
+                loop {
+                    match bytes.next() {
+                        Some(b'"') => break,
+                        Some(b'\\') => raw.push(*bytes.next().ok_or_else(malformed)?),
+                        Some(&byte) => raw.push(byte),
+                        None => return Err(malformed()),
+                    }
+                }
+                value = String::from_utf8_lossy(&raw).to_string();
+                rest = bytes.as_slice();
+            } else {
+                let value_len = take_token(rest);
+                if value_len == 0 {
+                    return Err(malformed());
+                }
+                value = String::from_utf8_lossy(&rest[..value_len]).to_string();
+                rest = &rest[value_len..];
+            }
+        }
+        extensions.push((name, value));
+    }
+}
+
+/// The value of the Content-Length header, if there is one.
+///
+/// A repeated header is accepted if every value is the same, as RFC 9112 §6.3 allows;
+/// anything else could be read differently by a proxy in front of the server.
+fn content_length(headers: &HeaderMap) -> Result<Option<usize>, EspressoRequestError> {
+    let Some(value) = headers.get_joined("Content-Length") else {
+        return Ok(None);
+    };
+    let invalid = || EspressoRequestError::MalformedRequest("Invalid Content-Length.".to_string());
+
+    let mut len: Option<usize> = None;
+    for item in value.split(',').map(str::trim) {
+        if item.is_empty() || !item.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let item: usize = item.parse().map_err(|_| invalid())?;
+        if len.is_some_and(|len| len != item) {
+            return Err(invalid());
+        }
+        len = Some(item);
+    }
+    Ok(len)
+}
+
+/// Reads a line ending in LF, with an optional CR before it, without the line ending.
+/// Returns `Ok(None)` if the reader is at its end before the first byte.
+fn read_line<R: BufRead>(
+    reader: &mut R,
+    limit: usize,
+) -> Result<Option<Vec<u8>>, EspressoRequestError> {
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        let available = match reader.fill_buf() {
+            Ok(available) => available,
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(err) => return Err(read_error(err, "Couldn't read request")),
+        };
+        if available.is_empty() {
+            if line.is_empty() {
+                return Ok(None);
+            }
+            return Err(EspressoRequestError::IncompleteRequest(
+                "Connection closed in the middle of a line.".to_string(),
+            ));
+        }
+
+        let (chunk, found) = match available.iter().position(|&byte| byte == b'\n') {
+            Some(end) => (&available[..=end], true),
+            None => (available, false),
+        };
+        if line.len() + chunk.len() > limit {
+            return Err(EspressoRequestError::UriTooLong);
+        }
+        line.extend_from_slice(chunk);
+        let used = chunk.len();
+        reader.consume(used);
+
+        if found {
+            line.pop();
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.contains(&b'\r') {
+                return Err(EspressoRequestError::MalformedRequest(
+                    "Bare CR in request.".to_string(),
+                ));
+            }
+            return Ok(Some(line));
+        }
+    }
+}
+
+/// The error for a failed read from the connection, which is a timeout if the client was too slow.
+fn read_error(err: io::Error, context: &str) -> EspressoRequestError {
+    if is_timeout(&err) {
+        return EspressoRequestError::Timeout;
+    }
+    EspressoRequestError::IncompleteRequest(format!("{context}: {err}"))
+}
+
+/// Whether `err` is a read that timed out. Depending on the platform, a socket read timeout
+/// fails with `WouldBlock` or `TimedOut`.
+pub(crate) fn is_timeout(err: &io::Error) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Parses `method SP request-target SP HTTP-version`.
+fn parse_request_line(
+    line: &[u8],
+) -> Result<(RequestMethod, String, String), EspressoRequestError> {
+    let malformed =
+        || EspressoRequestError::MalformedRequest("Malformed request line.".to_string());
+
+    let mut items = line.split(|&byte| byte == b' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (items.next(), items.next(), items.next(), items.next())
+    else {
+        return Err(malformed());
+    };
+
+    // Anything that isn't a token fails to parse.
+    let method: RequestMethod = std::str::from_utf8(method)
+        .map_err(|_| malformed())?
+        .parse()
+        .map_err(|_| malformed())?;
+
+    if target.is_empty() || !target.iter().all(|&byte| byte.is_ascii_graphic()) {
+        return Err(malformed());
+    }
+
+    match version {
+        [b'H', b'T', b'T', b'P', b'/', major, b'.', minor]
+            if major.is_ascii_digit() && minor.is_ascii_digit() =>
+        {
+            if *major != b'1' {
+                return Err(EspressoRequestError::UnsupportedVersion(
+                    String::from_utf8_lossy(version).to_string(),
+                ));
+            }
+        }
+        _ => return Err(malformed()),
+    }
+
+    // Every byte was checked to be ASCII above, so these conversions can't fail.
+    Ok((
+        method,
+        String::from_utf8_lossy(target).to_string(),
+        String::from_utf8_lossy(version).to_string(),
+    ))
+}
+
+/// Parses `field-name ":" OWS field-value OWS`, returning the name and the value.
+fn parse_header_field(line: &[u8]) -> Result<(String, String), EspressoRequestError> {
+    if line[0] == b' ' || line[0] == b'\t' {
+        return Err(EspressoRequestError::MalformedRequest(
+            "Obsolete line folding is not accepted.".to_string(),
+        ));
+    }
+    let Some(colon) = line.iter().position(|&byte| byte == b':') else {
+        return Err(EspressoRequestError::MalformedRequest(
+            "Header field without a colon.".to_string(),
+        ));
+    };
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    // This also rejects whitespace between the name and the colon, as RFC 9112 §5.1 requires.
+    if name.is_empty() || !name.iter().all(|&byte| is_tchar(byte)) {
+        return Err(EspressoRequestError::MalformedRequest(
+            "Invalid header field name.".to_string(),
+        ));
+    }
+
+    let value = value.trim_ascii();
+    if value
+        .iter()
+        .any(|&byte| (byte < 0x20 && byte != b'\t') || byte == 0x7F)
+    {
+        return Err(EspressoRequestError::MalformedRequest(
+            "Invalid character in header field value.".to_string(),
+        ));
+    }
+
+    let name = String::from_utf8_lossy(name).to_string();
+    // obs-text is allowed in values; bytes that aren't UTF-8 are read as ISO-8859-1.
+    let value = match std::str::from_utf8(value) {
+        Ok(value) => value.to_string(),
+        Err(_) => value.iter().map(|&byte| byte as char).collect(),
+    };
+    Ok((name, value))
+}
+
+/// Whether `byte` may appear in a token, such as a method or a header field name.
+pub(crate) fn is_tchar(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
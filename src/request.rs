@@ -1,15 +1,22 @@
-use atoi::atoi;
 use std::{
-    collections::HashMap,
-    io::{BufRead, BufReader, Read},
+    collections::VecDeque,
+    io::{self, BufRead, BufReader, ErrorKind, Read},
     net::TcpStream,
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
 };
 
 use crate::{
+    config::EspressoConfig,
     error::{EspressoProcessingError, EspressoRequestError},
+    headers::HeaderMap,
+    parser::{self, BodyFraming, ChunkedDecoder, RequestBody, RequestHead},
     response::ResponseWriter,
+    route::Params,
+    url::Query,
 };
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum RequestMethod {
     GET,
     POST,
@@ -18,16 +25,134 @@ pub enum RequestMethod {
     DELETE,
     OPTIONS,
     HEAD,
+    TRACE,
+    CONNECT,
+    /// A method outside RFC 9110 and RFC 5789, such as WebDAV's `PROPFIND` or `PURGE`.
+    /// Build it with [`str::parse`], which picks the standard variant for standard names.
+    Extension(String),
+}
+
+impl RequestMethod {
+    /// The method name as it appears on the request line.
+    pub fn as_str(&self) -> &str {
+        match self {
+            RequestMethod::GET => "GET",
+            RequestMethod::POST => "POST",
+            RequestMethod::PUT => "PUT",
+            RequestMethod::PATCH => "PATCH",
+            RequestMethod::DELETE => "DELETE",
+            RequestMethod::OPTIONS => "OPTIONS",
+            RequestMethod::HEAD => "HEAD",
+            RequestMethod::TRACE => "TRACE",
+            RequestMethod::CONNECT => "CONNECT",
+            RequestMethod::Extension(method) => method,
+        }
+    }
+}
+
+impl FromStr for RequestMethod {
+    type Err = EspressoRequestError;
+
+    /// Parses a method name. Method names are case-sensitive, so `get` is an extension method.
+    fn from_str(method: &str) -> Result<RequestMethod, EspressoRequestError> {
+        Ok(match method {
+            "GET" => RequestMethod::GET,
+            "POST" => RequestMethod::POST,
+            "PUT" => RequestMethod::PUT,
+            "PATCH" => RequestMethod::PATCH,
+            "DELETE" => RequestMethod::DELETE,
+            "OPTIONS" => RequestMethod::OPTIONS,
+            "HEAD" => RequestMethod::HEAD,
+            "TRACE" => RequestMethod::TRACE,
+            "CONNECT" => RequestMethod::CONNECT,
+            _ if !method.is_empty() && method.bytes().all(parser::is_tchar) => {
+                RequestMethod::Extension(method.to_string())
+            }
+            _ => {
+                return Err(EspressoRequestError::MalformedRequest(format!(
+                    "{method:?} is not a valid method name."
+                )))
+            }
+        })
+    }
+}
+
+/// The read half of a connection. Shared with the request whose body is streamed to its handler.
+pub(crate) type SharedReader = Arc<Mutex<BufReader<ConnReader>>>;
+
+/// Reads from a connection, waiting at most `idle_timeout` for each read, and failing with
+/// `TimedOut` once `deadline` has passed.
+pub(crate) struct ConnReader {
+    tcp: TcpStream,
+    idle_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    /// The read timeout currently set on the socket.
+    applied: Option<Duration>,
+    /// Bytes read off the connection before it came here, which are read first.
+    pending: VecDeque<u8>,
+}
+
+impl ConnReader {
+    fn new(tcp: TcpStream, idle_timeout: Option<Duration>) -> ConnReader {
+        ConnReader {
+            tcp,
+            idle_timeout,
+            deadline: None,
+            applied: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Lets reads wait as long as it takes, as after an upgrade to a protocol with idle
+    /// connections of its own.
+    pub(crate) fn clear_timeouts(&mut self) {
+        self.idle_timeout = None;
+        self.deadline = None;
+    }
+}
+
+impl Read for ConnReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.pending.is_empty() {
+            return self.pending.read(buf);
+        }
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "Read deadline passed."));
+                }
+                Some(
+                    self.idle_timeout
+                        .map_or(remaining, |idle| idle.min(remaining)),
+                )
+            }
+            None => self.idle_timeout,
+        };
+        if timeout != self.applied {
+            self.tcp.set_read_timeout(timeout)?;
+            self.applied = timeout;
+        }
+        self.tcp.read(buf)
+    }
 }
 
 pub struct EspressoStream {
-    reader: BufReader<TcpStream>,
+    reader: SharedReader,
     pub writer: ResponseWriter,
     tcp: TcpStream,
+    config: EspressoConfig,
+    /// How many requests have been read from the connection.
+    pub(crate) requests: usize,
 }
 impl EspressoStream {
     /// Creates a new [`EspressoStream`] wrapping the underlying [`TcpStream`] and provides a [`BufReader`] and [`ResponseWriter`] instance.
     pub fn new(tcp_stream: TcpStream) -> EspressoStream {
+        EspressoStream::with_config(tcp_stream, EspressoConfig::default())
+    }
+
+    /// Creates a new [`EspressoStream`] that reads requests within the limits set by `config`.
+    pub fn with_config(tcp_stream: TcpStream, config: EspressoConfig) -> EspressoStream {
         // These references are essentially the same underlying TcpStream.
         let read_stream = tcp_stream
             .try_clone()
@@ -36,14 +161,22 @@ impl EspressoStream {
             .try_clone()
             .expect("The TCP stream was unable to be cloned.");
         EspressoStream {
-            reader: BufReader::new(read_stream),
+            reader: Arc::new(Mutex::new(BufReader::new(ConnReader::new(
+                read_stream,
+                config.idle_timeout,
+            )))),
             writer: ResponseWriter::new(write_stream),
             tcp: tcp_stream
                 .try_clone()
                 .expect("Unable to clone the TCP stream."),
+            config,
+            requests: 0,
         }
     }
 
+    /// A new stream on the same connection, with buffers of its own. Fails if the connection
+    /// can't be duplicated.
+    #[allow(clippy::should_implement_trait)]
     pub fn clone(&self) -> Result<EspressoStream, EspressoProcessingError> {
         if let (Ok(reader_stream), Ok(writer_stream), Ok(cloned_tcp)) = (
             self.tcp.try_clone(),
@@ -51,9 +184,14 @@ impl EspressoStream {
             self.tcp.try_clone(),
         ) {
             return Ok(EspressoStream {
-                reader: BufReader::new(reader_stream),
+                reader: Arc::new(Mutex::new(BufReader::new(ConnReader::new(
+                    reader_stream,
+                    self.config.idle_timeout,
+                )))),
                 writer: ResponseWriter::new(writer_stream),
                 tcp: cloned_tcp,
+                config: self.config.clone(),
+                requests: 0,
             });
         }
 
@@ -66,80 +204,259 @@ pub struct EspressoStreamFrame {
 }
 
 impl EspressoStream {
-    pub fn next(&mut self) -> Option<EspressoStreamFrame> {
-        let mut body: Option<String> = Some(String::new());
-        let mut headers: HashMap<String, String> = HashMap::new();
-
-        let mut buf: String = String::new();
-        let (method, resource, protocol) = {
-            self.reader
-                .read_line(&mut buf)
-                .expect("Couldn't read request.");
-            let items: Vec<&str> = buf.split(" ").take(3).collect();
-            (
-                match items[0].to_uppercase().as_str() {
-                    "GET" => RequestMethod::GET,
-                    "PUT" => RequestMethod::PUT,
-                    "POST" => RequestMethod::POST,
-                    "DELETE" => RequestMethod::DELETE,
-                    _ => {
-                        return None;
-                    }
-                },
-                items[1],
-                items[2],
-            )
+    /// Takes the connection apart, for speaking another protocol on it after an upgrade. The
+    /// reader keeps whatever the client sent after the request that was upgraded.
+    pub(crate) fn into_parts(self) -> (SharedReader, TcpStream, EspressoConfig) {
+        lock(&self.reader).get_mut().clear_timeouts();
+        (self.reader, self.tcp, self.config)
+    }
+
+    /// Carries on in blocking mode with a connection the epoll engine has read `pending` from,
+    /// after `requests` requests.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn resume(
+        tcp: TcpStream,
+        pending: Vec<u8>,
+        config: EspressoConfig,
+        requests: usize,
+    ) -> io::Result<EspressoStream> {
+        tcp.set_nonblocking(false)?;
+        let mut stream = EspressoStream::with_config(tcp, config);
+        lock(&stream.reader).get_mut().pending = pending.into();
+        stream.requests = requests;
+        Ok(stream)
+    }
+
+    /// Gives the connection back to the epoll engine, along with whatever was read from it past
+    /// the last request.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn into_connection(self) -> io::Result<(TcpStream, Vec<u8>)> {
+        let pending = {
+            let mut reader = lock(&self.reader);
+            let mut pending = reader.buffer().to_vec();
+            pending.extend(reader.get_mut().pending.drain(..));
+            pending
         };
-        let mut buf: String = String::new();
-        // Read headers
-        while !buf.is_empty() {
-            buf.clear();
-            match self.reader.read_line(&mut buf) {
-                Err(_) => {
-                    return None;
-                }
-                _ => (),
+        self.tcp.set_read_timeout(None)?;
+        self.tcp.set_nonblocking(true)?;
+        Ok((self.tcp, pending))
+    }
+
+    /// Reads the next request from the connection, including its body.
+    ///
+    /// Returns `Ok(None)` once the client has closed the connection between requests, or has
+    /// sent nothing for [`EspressoConfig::idle_timeout`]. An error means the request couldn't be
+    /// parsed, or its head took longer than [`EspressoConfig::header_timeout`]; the connection is
+    /// then out of step with the client and shouldn't be read from again.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Result<Option<EspressoStreamFrame>, EspressoRequestError> {
+        self.next_streaming(|_| false)
+    }
+
+    /// Same as [`EspressoStream::next`], except that the body is left on the connection when
+    /// `stream_body` returns `true` for the request. The handler then reads it through
+    /// [`EspressoRequest::body_reader`], and [`EspressoStream::finish`] must be called before the
+    /// next request is read.
+    pub fn next_streaming(
+        &mut self,
+        stream_body: impl FnOnce(&EspressoRequest) -> bool,
+    ) -> Result<Option<EspressoStreamFrame>, EspressoRequestError> {
+        self.read_next(|| {}, stream_body)
+    }
+
+    /// Same as [`EspressoStream::next_streaming`], calling `started` as soon as the first byte
+    /// of the request has arrived, before the rest of it is read.
+    pub(crate) fn read_next(
+        &mut self,
+        started: impl FnOnce(),
+        stream_body: impl FnOnce(&EspressoRequest) -> bool,
+    ) -> Result<Option<EspressoStreamFrame>, EspressoRequestError> {
+        let mut reader = lock(&self.reader);
+        if !wait_for_request(&mut reader) {
+            return Ok(None);
+        }
+        started();
+        reader.get_mut().deadline = self
+            .config
+            .header_timeout
+            .map(|timeout| Instant::now() + timeout);
+        let head = parser::parse_head(
+            &mut *reader,
+            self.config.max_request_line,
+            self.config.max_header_size,
+        );
+        reader.get_mut().deadline = None;
+        let Some(mut head) = head? else {
+            return Ok(None);
+        };
+        self.requests += 1;
+
+        forward_peer(&mut head.headers, &self.tcp);
+        let framing = parser::body_framing(&head.headers)?;
+        let mut request = EspressoRequest::from_parts(head, None);
+        match framing {
+            BodyFraming::None => {}
+            _ if stream_body(&request) => {
+                request.body_stream = Some(Mutex::new(StreamingBody::new(
+                    Arc::clone(&self.reader),
+                    framing,
+                    &self.config,
+                )));
             }
+            _ => {
+                let body = parser::read_body(&mut *reader, framing, &self.config)?;
+                request.set_body(body);
+            }
+        }
+
+        Ok(Some(EspressoStreamFrame { request }))
+    }
+
+    /// Skips whatever the handler left unread of a streamed request body, so the next request
+    /// can be read. Returns `false` if the connection has to be closed instead: the body couldn't
+    /// be read, or more than [`EspressoConfig::max_body_size`] bytes of it were left.
+    pub fn finish(&mut self, request: &EspressoRequest) -> bool {
+        match &request.body_stream {
+            Some(body) => lock(body).drain(self.config.max_body_size),
+            None => true,
+        }
+    }
+}
+
+/// Waits for the first byte of the next request, returning `false` if the client closed the
+/// connection or stayed idle for longer than the idle timeout instead. Either way the connection
+/// is done with, without an answer.
+fn wait_for_request(reader: &mut BufReader<ConnReader>) -> bool {
+    loop {
+        match reader.fill_buf() {
+            Ok(available) => return !available.is_empty(),
+            Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => return false,
+        }
+    }
+}
+
+/// Adds the address of the client as `X-Forwarded-For`, unless a proxy in front of the server
+/// already did.
+pub(crate) fn forward_peer(headers: &mut HeaderMap, tcp: &TcpStream) {
+    if !headers.contains("X-Forwarded-For") {
+        if let Ok(peer) = tcp.peer_addr() {
+            headers.insert("X-Forwarded-For", &peer.ip().to_canonical().to_string());
+        }
+    }
+}
+
+/// Locks a mutex even if a thread panicked while holding it. A poisoned reader is caught by the
+/// framing state of the body it was reading instead, and the data behind the other mutexes of the
+/// server, such as those of the thread pools, stays consistent across a panic.
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A request body that is still on the connection, read as the handler asks for it.
+pub(crate) struct StreamingBody {
+    reader: SharedReader,
+    state: StreamingState,
+}
+
+enum StreamingState {
+    /// Bytes of a Content-Length body not read yet.
+    Length(usize),
+    Chunked(ChunkedDecoder),
+    /// Reading the body failed, so the rest of the connection can't be trusted.
+    Failed,
+}
+
+impl StreamingBody {
+    fn new(reader: SharedReader, framing: BodyFraming, config: &EspressoConfig) -> StreamingBody {
+        let state = match framing {
+            BodyFraming::None => StreamingState::Length(0),
+            BodyFraming::Length(len) => StreamingState::Length(len),
+            BodyFraming::Chunked => StreamingState::Chunked(ChunkedDecoder::new(config)),
+        };
+        StreamingBody { reader, state }
+    }
 
-            let header_parts: Vec<&str> = buf.split(": ").collect();
-            headers.insert(
-                header_parts[0].to_uppercase().to_string(),
-                header_parts[1].to_string(),
-            );
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
         }
-        if let None = headers.get("X-Forwarded-For") {
-            headers.insert(
-                "X-Forwarded-For".to_string(),
-                self.tcp
-                    .peer_addr()
-                    .unwrap()
-                    .ip()
-                    .to_canonical()
-                    .to_string(),
-            );
+        let mut reader = lock(&self.reader);
+        let result = match &mut self.state {
+            StreamingState::Length(0) => return Ok(0),
+            StreamingState::Length(remaining) => {
+                let limit = buf.len().min(*remaining);
+                match reader.read(&mut buf[..limit]) {
+                    Ok(0) => Err(io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "Connection closed before the end of the body.",
+                    )),
+                    Ok(read) => {
+                        *remaining -= read;
+                        Ok(read)
+                    }
+                    Err(err) => Err(err),
+                }
+            }
+            StreamingState::Chunked(decoder) => decoder
+                .read(&mut *reader, buf)
+                .map_err(|err| io::Error::new(ErrorKind::InvalidData, format!("{err:?}"))),
+            StreamingState::Failed => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    "Reading the body failed earlier.",
+                ))
+            }
+        };
+        if result
+            .as_ref()
+            .is_err_and(|err| err.kind() != ErrorKind::Interrupted)
+        {
+            self.state = StreamingState::Failed;
         }
-        // Reads body
-        let mut body_len: Option<usize> = None;
-        if let Some(len_str) = headers.get("CONTENT-LENGTH") {
-            if let Some(len) = atoi::<u32>(len_str.as_bytes()) {
-                let mut buf: Vec<u8> = Vec::with_capacity(len as usize);
-                body_len = Some(len.try_into().unwrap());
-                let _ = self.reader.read_exact(&mut buf);
+        result
+    }
+
+    /// Reads and discards the rest of the body, up to `limit` bytes.
+    fn drain(&mut self, limit: usize) -> bool {
+        match &self.state {
+            StreamingState::Length(remaining) if *remaining > limit => return false,
+            StreamingState::Chunked(decoder) if decoder.is_done() => return true,
+            StreamingState::Failed => return false,
+            _ => {}
+        }
+        let mut buf = [0; 8 * 1024];
+        let mut drained = 0;
+        loop {
+            match self.read(&mut buf) {
+                Ok(0) => return true,
+                Ok(read) => drained += read,
+                Err(err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+            if drained > limit {
+                return false;
             }
-        } else {
-            body.take();
         }
+    }
+}
 
-        Some(EspressoStreamFrame {
-            request: EspressoRequest {
-                headers,
-                method,
-                resource: resource.to_string(),
-                protocol_ver: protocol.to_string(),
-                body,
-                body_len,
-            },
-        })
+/// Reads the body of a request. See [`EspressoRequest::body_reader`].
+pub struct BodyReader<'a> {
+    source: BodySource<'a>,
+}
+
+enum BodySource<'a> {
+    Buffered(&'a [u8]),
+    Streaming(&'a Mutex<StreamingBody>),
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.source {
+            BodySource::Buffered(bytes) => bytes.read(buf),
+            BodySource::Streaming(body) => lock(body).read(buf),
+        }
     }
 }
 
@@ -149,17 +466,123 @@ impl EspressoStream {
 //     }
 // }
 pub struct EspressoRequest {
-    pub headers: HashMap<String, String>,
+    pub headers: HeaderMap,
     pub method: RequestMethod,
+    /// The request target exactly as it was sent, including the query string.
     pub resource: String,
+    /// The path of the target, percent-decoded and with dot segments resolved. Routing is done on it.
+    pub(crate) normalized_path: String,
+    pub(crate) query: Query,
     pub protocol_ver: String,
-    pub body: Option<String>,
-    pub body_len: Option<usize>,
+    /// The bytes of the request body, `None` if the request has none.
+    pub body: Option<Vec<u8>>,
+    /// Extensions sent with the chunks of a chunked body, in order. Extensions without a value
+    /// have an empty one.
+    pub chunk_extensions: Vec<(String, String)>,
+    /// Fields of the trailer section of a chunked body.
+    pub trailers: HeaderMap,
+    /// The body still on the connection, for routes registered with
+    /// [`crate::router::Router::route_streaming`].
+    pub(crate) body_stream: Option<Mutex<StreamingBody>>,
+    pub(crate) params: Params,
+    pub(crate) base_url: String,
+    pub(crate) route: Option<String>,
 }
 
 impl EspressoRequest {
-    pub fn get_header(&self) -> Option<String> {
-        Some("".to_string())
+    pub(crate) fn from_parts(head: RequestHead, body: Option<RequestBody>) -> EspressoRequest {
+        let mut request = EspressoRequest {
+            headers: head.headers,
+            method: head.method,
+            resource: head.target,
+            normalized_path: head.path,
+            query: head.query.as_deref().map(Query::parse).unwrap_or_default(),
+            protocol_ver: head.version,
+            body: None,
+            chunk_extensions: Vec::new(),
+            trailers: HeaderMap::new(),
+            body_stream: None,
+            params: Params::default(),
+            base_url: String::new(),
+            route: None,
+        };
+        request.set_body(body);
+        request
+    }
+
+    pub(crate) fn set_body(&mut self, body: Option<RequestBody>) {
+        if let Some(body) = body {
+            self.body = Some(body.data);
+            self.chunk_extensions = body.extensions;
+            self.trailers = body.trailers;
+        }
+    }
+
+    /// Parameters captured from the path by the route currently handling the request.
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// The pattern of the route handling the request, e.g. `/users/:id`. `None` if no route matched.
+    pub fn route(&self) -> Option<&str> {
+        self.route.as_deref()
+    }
+
+    /// The prefix of the router the request was routed into, e.g. `/api` for a router mounted
+    /// there. Empty when the route is registered on the application itself.
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// The requested path as seen by the router handling it, with [`EspressoRequest::base_url`] removed.
+    /// Percent-decoded, except for `%2F`, and without the query string.
+    pub fn path(&self) -> &str {
+        match &self.normalized_path[self.base_url.len()..] {
+            "" => "/",
+            path => path,
+        }
+    }
+
+    /// The requested path including [`EspressoRequest::base_url`], decoded like [`EspressoRequest::path`].
+    pub fn full_path(&self) -> &str {
+        &self.normalized_path
+    }
+
+    /// The parameters of the query string. Empty if the target has none.
+    pub fn query(&self) -> &Query {
+        &self.query
+    }
+
+    /// Reads the request body. For routes registered with
+    /// [`crate::router::Router::route_streaming`] the body is read from the connection as the
+    /// handler asks for it, so it can be larger than [`EspressoConfig::max_body_size`]. For other
+    /// routes it reads [`EspressoRequest::body_bytes`].
+    ///
+    /// Trailers of a streamed chunked body aren't kept.
+    pub fn body_reader(&self) -> BodyReader<'_> {
+        let source = match &self.body_stream {
+            Some(body) => BodySource::Streaming(body),
+            None => BodySource::Buffered(self.body_bytes()),
+        };
+        BodyReader { source }
+    }
+
+    /// The request body, or an empty slice if the request has none. Always empty for routes
+    /// registered with [`crate::router::Router::route_streaming`].
+    pub fn body_bytes(&self) -> &[u8] {
+        self.body.as_deref().unwrap_or_default()
+    }
+
+    /// The request body as text. Fails with a `400 Bad Request` error if it isn't valid UTF-8.
+    pub fn body_text(&self) -> Result<&str, EspressoRequestError> {
+        std::str::from_utf8(self.body_bytes()).map_err(|_| {
+            EspressoRequestError::MalformedRequest("Request body is not valid UTF-8.".to_string())
+        })
+    }
+
+    /// The first value of the header `name`, compared case-insensitively.
+    pub fn get_header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
     }
 }
 
@@ -168,123 +591,30 @@ impl<'a> TryFrom<&'a [u8]> for EspressoRequest {
     type Error = EspressoRequestError;
 
     fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let config = EspressoConfig::default();
         let mut reader = BufReader::new(value);
-        let mut headers: HashMap<String, String> = HashMap::new();
-
-        let mut buf: String = String::new();
-        let (method, resource, protocol) = {
-            if let Err(_err) = reader.read_line(&mut buf) {
-                return Err(EspressoRequestError::MalformedRequest(
-                    "TCP Stream was empty.".to_string(),
-                ));
-            }
-            let items: Vec<&str> = buf.split(" ").take(3).collect();
-            (
-                match items[0].to_uppercase().as_str() {
-                    "GET" => RequestMethod::GET,
-                    "PUT" => RequestMethod::PUT,
-                    "POST" => RequestMethod::POST,
-                    "DELETE" => RequestMethod::DELETE,
-                    _ => {
-                        return Err(EspressoRequestError::MalformedRequest(
-                            "Request method not supported".to_string(),
-                        ));
-                    }
-                },
-                items[1],
-                items[2],
-            )
+        let Some(head) =
+            parser::parse_head(&mut reader, config.max_request_line, config.max_header_size)?
+        else {
+            return Err(EspressoRequestError::IncompleteRequest(
+                "Request was empty.".to_string(),
+            ));
         };
-        let mut buf: String = String::new();
-        // Read headers
-        while !buf.is_empty() {
-            buf.clear();
-            let _ = reader.read_line(&mut buf);
-
-            let header_parts: Vec<&str> = buf.split(": ").collect();
-            headers.insert(
-                header_parts[0].to_ascii_uppercase().to_string(),
-                header_parts[1].to_string(),
-            );
-        }
 
-        // Reads body
-        let mut body: Option<String> = None;
-        let mut body_len: Option<usize> = None;
-        buf.clear();
-        if let Ok(read) = reader.read_to_string(&mut buf) {
-            body = Some(buf);
-            body_len = Some(read);
-        }
+        // Without a Content-Length or Transfer-Encoding, everything after the headers is the body.
+        let framing = parser::body_framing(&head.headers)?;
+        let body = match parser::read_body(&mut reader, framing, &config)? {
+            Some(body) => Some(body),
+            None => {
+                let mut data: Vec<u8> = Vec::new();
+                reader.read_to_end(&mut data).ok().map(|_| RequestBody {
+                    data,
+                    extensions: Vec::new(),
+                    trailers: HeaderMap::new(),
+                })
+            }
+        };
 
-        // for (ind, line) in reader.lines().enumerate() {
-        //     match line {
-        //         Ok(line) => {
-        //             if line.is_empty() {
-        //                 break;
-        //             }
-        //             match ind {
-        //                 0 => {
-        //                     let line = line.to_owned();
-        //                     let line_parts: Vec<&str> = line.split(" ").collect();
-        //                     method = match line_parts[0].to_uppercase().as_str() {
-        //                         "GET" => Some(RequestMethod::GET),
-        //                         "POST" => Some(RequestMethod::POST),
-        //                         "PUT" => Some(RequestMethod::PUT),
-        //                         "DELETE" => Some(RequestMethod::DELETE),
-        //                         _ => {
-        //                             return Err(
-        //                                 EspressoRequestError::MalformedRequest(
-        //                                     "Request method not supported.".to_string()
-        //                                 )
-        //                             );
-        //                         }
-        //                     };
-        //                     resource.push_str(line_parts[1]);
-        //                     protocol_ver.push_str(line_parts[2]);
-        //                 }
-        //                 1.. => {
-        //                     if line.is_empty() {
-        //                         if break_found {
-        //                             break;
-        //                         } else {
-        //                             break_found = true;
-        //                             continue;
-        //                         }
-        //                     }
-        //                     if !break_found {
-        //                         let header_parts: Vec<&str> = line.split(": ").collect();
-        //                         headers.insert(
-        //                             header_parts[0].to_ascii_uppercase().to_string(),
-        //                             header_parts[1].to_string()
-        //                         );
-        //                     } else {
-        //                         if let Some(content_length) = headers.get("CONTENT-LENGTH") {
-        //                             if let Some(len) = atoi::atoi::<u32>(content_length.as_bytes()) {
-        //                                 // Insert the body into the Request data fields
-        //                                 line.
-        //                             }
-        //                         }
-        //                     }
-        //                 }
-        //             }
-        //         }
-        //         Err(_) => {
-        //             return Err(
-        //                 EspressoRequestError::MalformedRequest(
-        //                     "Request is misaligned or incomplete.".to_string()
-        //                 )
-        //             );
-        //         }
-        //     }
-        // }
-        Ok(EspressoRequest {
-            headers,
-            method,
-            resource: resource.to_string(),
-            protocol_ver: protocol.to_string(),
-            body,
-            body_len,
-        })
+        Ok(EspressoRequest::from_parts(head, body))
     }
 }
@@ -1,129 +1,368 @@
-use std::{ collections::HashMap, net::TcpStream };
+use std::{
+    io::{self, IoSlice, Read},
+    net::TcpStream,
+};
+
+use crate::{
+    body::{Body, BodyWriter, Framing},
+    headers::HeaderMap,
+    parser::is_tchar,
+    request::{EspressoRequest, EspressoStream},
+    sse::SseSender,
+    status::StatusCode,
+};
 pub struct EspressoResponse {
-    pub status: usize,
+    pub status: StatusCode,
+    /// The reason phrase sent after the status code.
     pub message: String,
-    pub body: String,
-    pub headers: HashMap<String, String>,
+    pub body: Body,
+    pub headers: HeaderMap,
+    /// Whether the response may take indefinitely long to write, such as an event stream, and so
+    /// shouldn't hold on to a worker of the pool.
+    pub(crate) long_lived: bool,
+    /// Takes over the connection once the response has been sent, if its status is
+    /// `101 Switching Protocols`.
+    pub(crate) upgrade: Option<UpgradeFn>,
+    /// Whether the response answers a HEAD request: it is sent without a body, and keeps the
+    /// `Content-Length` or `Transfer-Encoding` of the body a GET would get.
+    pub(crate) head: bool,
 }
 
+/// Speaks the protocol a connection was upgraded to.
+pub(crate) type UpgradeFn = Box<dyn FnOnce(&EspressoRequest, EspressoStream) + Send>;
+
 impl EspressoResponse {
+    /// Sets the status code and its canonical reason phrase. Codes without one get an empty
+    /// reason phrase.
+    ///
+    /// Panics if `status` isn't between 100 and 999. See [`EspressoResponse::try_status`].
     pub fn status(&mut self, status: usize) {
+        if let Err(err) = self.try_status(status) {
+            panic!("Unable to set status: {err:?}");
+        }
+    }
+
+    /// Like [`EspressoResponse::status`], but returns an error for codes outside 100 to 999.
+    pub fn try_status(&mut self, status: usize) -> Result<(), EspressoResponseError> {
+        self.set_status(StatusCode::try_from(status)?);
+        Ok(())
+    }
+
+    /// Sets the status code and its canonical reason phrase.
+    pub fn set_status(&mut self, status: StatusCode) {
         self.status = status;
+        self.message = status.canonical_reason().unwrap_or_default().to_string();
+    }
 
-        match status {
-            200 => {
-                self.message = "OK".to_string();
-            }
-            400 => {
-                self.message = "BAD REQUEST".to_string();
-            }
-            _ => {}
-        }
+    /// Replaces the reason phrase of the current status. Control characters other than tab
+    /// can't be part of a reason phrase and are left out.
+    pub fn reason(&mut self, reason: &str) {
+        self.message = reason
+            .chars()
+            .filter(|c| *c == '\t' || !c.is_control())
+            .collect();
     }
 
     pub fn send(&mut self, message: &str) {
-        self.body.push_str(message);
+        self.body.append(message.as_bytes());
+    }
+
+    /// Adds `bytes` to the body, which may hold any binary data.
+    pub fn send_bytes(&mut self, bytes: &[u8]) {
+        self.body.append(bytes);
+    }
+
+    /// Replaces the body, for example with a file from [`Body::open`] or a reader from
+    /// [`Body::reader`].
+    pub fn set_body(&mut self, body: impl Into<Body>) {
+        self.body = body.into();
+    }
+
+    /// Streams the body from `write` once the headers have been sent. See [`Body::stream`].
+    pub fn stream(
+        &mut self,
+        write: impl FnOnce(&mut BodyWriter<'_>) -> io::Result<()> + Send + 'static,
+    ) {
+        self.body = Body::stream(write);
+    }
+
+    /// Answers with a `text/event-stream` of the events `events` sends once the headers have
+    /// been sent, for as long as it runs. The `Last-Event-ID` of `request` is passed along.
+    ///
+    /// The stream runs on a thread of its own rather than on a worker of the pool, so open
+    /// streams don't keep other connections from being served. At most
+    /// [`crate::config::EspressoConfig::max_long_lived`] run at once, counting upgraded
+    /// connections; requests over the limit are answered with `503 Service Unavailable`.
+    pub fn sse(
+        &mut self,
+        request: &EspressoRequest,
+        events: impl FnOnce(&mut SseSender<'_>) -> io::Result<()> + Send + 'static,
+    ) {
+        let last_event_id = request.headers.get("Last-Event-ID").map(str::to_string);
+        self.set_header("Content-Type", "text/event-stream");
+        self.set_header("Cache-Control", "no-cache");
+        self.body = Body::stream(move |out| events(&mut SseSender::new(out, last_event_id)));
+        self.long_lived = true;
+    }
+
+    /// Replaces the body with `value`, serialized, and sets `Content-Type` to its media type.
+    pub fn serialize<T: Serialize + ?Sized>(&mut self, value: &T) {
+        self.set_header("Content-Type", value.content_type());
+        self.body = value.serialize();
     }
 
+    /// Sets the header `header_name`, replacing any value it already had.
     pub fn set_header(&mut self, header_name: &str, header_value: &str) {
-        self.headers.insert(header_name.to_string(), header_value.to_string());
+        self.headers.insert(header_name, header_value);
+    }
+
+    /// Adds a value to the header `header_name`, keeping the values it already had. For headers
+    /// that are sent once per value, such as `Set-Cookie`.
+    pub fn append_header(&mut self, header_name: &str, header_value: &str) {
+        self.headers.append(header_name, header_value);
     }
 }
-pub struct ResponseWriter {
-    buffer: Vec<u8>,
-    tcp_stream: TcpStream,
+impl EspressoResponse {
+    pub fn new() -> EspressoResponse {
+        EspressoResponse {
+            status: StatusCode::OK,
+            message: "OK".to_string(),
+            body: Body::default(),
+            headers: HeaderMap::new(),
+            long_lived: false,
+            upgrade: None,
+            head: false,
+        }
+    }
 }
 
-pub trait Write {
-    fn write_bytes(&mut self, bytes: &[u8]);
-    fn write_str(&mut self, str: &str);
-    fn write_string(&mut self, string: String);
+impl Default for EspressoResponse {
+    fn default() -> EspressoResponse {
+        EspressoResponse::new()
+    }
 }
 
-impl<'a> ResponseWriter {
-    pub fn new(stream: TcpStream) -> ResponseWriter {
-        ResponseWriter { buffer: Vec::new(), tcp_stream: stream }
-    }
+/// Serializes responses onto a connection.
+///
+/// The status line and header section are put together in a buffer that is reused from one
+/// response to the next, then written along with the body in one vectored write.
+pub struct ResponseWriter<W: io::Write = TcpStream> {
+    head: Vec<u8>,
+    chunk: Vec<u8>,
+    writer: W,
+}
 
-    pub fn flush(&mut self) -> Result<usize, EspressoResponseError> {
-        match std::io::Write::write_all(&mut self.tcp_stream, self.buffer.as_ref()) {
-            Ok(()) => {
-                let wrote = self.buffer.len();
-                self.buffer.clear();
-                Ok(wrote)
-            }
-            Err(_) => Err(EspressoResponseError::BufferError),
+/// The most a chunk of a body read from a reader holds.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+impl<W: io::Write> ResponseWriter<W> {
+    pub fn new(writer: W) -> ResponseWriter<W> {
+        ResponseWriter {
+            head: Vec::new(),
+            chunk: Vec::new(),
+            writer,
         }
     }
 
-    pub fn clear(&mut self) {
-        self.buffer.clear();
+    /// The connection responses are written to.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// The connection responses are written to, mutably.
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
     }
 
-    pub fn write_response(&mut self, response: EspressoResponse) {
-        self.write_string(format!("HTTP/1.1 {} {}\r\n", response.status, response.message));
-        if let None = response.headers.get("CONTENT-LENGTH") {
-            self.write_string(format!("Content-Length: {}", response.body.len()));
+    /// Writes `response` and flushes the connection.
+    ///
+    /// A body whose length is known is sent with that length, replacing any `Content-Length` the
+    /// response had. One of unknown length is held to the `Content-Length` the response has, and
+    /// is otherwise sent chunked. A response that has `Connection: close` and a body of unknown
+    /// length is instead ended by closing the connection, which is how HTTP/1.0 clients expect
+    /// it. A response that has a `Transfer-Encoding` already frames its body itself. Responses whose status doesn't
+    /// allow content (1xx, 204 and 304) are sent without a body, and 1xx and 204 without a
+    /// `Content-Length` either. Header fields whose name isn't a valid token are left out, and
+    /// CR, LF and NUL are removed from values, so a header can't smuggle in another field or end
+    /// the header section early.
+    ///
+    /// The status line and headers are sent before a reader or stream body is produced. An
+    /// error is returned if the body can't be produced or doesn't match its length. Part of the
+    /// response may have been sent by then, so the connection can't be used any more.
+    pub fn write_response(&mut self, response: EspressoResponse) -> io::Result<()> {
+        let status = response.status;
+        let content = allows_content(status) && !response.head;
+        let length_allowed = !status.is_informational() && status != StatusCode::NO_CONTENT;
+        let framing = match response.body.len() {
+            _ if response.head || response.headers.contains("Transfer-Encoding") => Framing::Raw,
+            Some(length) => Framing::Length(length),
+            None if response.headers.contains("Content-Length") => {
+                match response.headers.content_length() {
+                    Some(length) => Framing::Length(length),
+                    None => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "response has an invalid Content-Length",
+                        ))
+                    }
+                }
+            }
+            None if response.headers.contains_token("Connection", "close") => Framing::Raw,
+            None => Framing::Chunked,
+        };
+
+        self.head.clear();
+        self.head.extend_from_slice(b"HTTP/1.1 ");
+        self.head.extend_from_slice(status.to_string().as_bytes());
+        self.head.push(b' ');
+        push_sanitized(&mut self.head, &response.message);
+        self.head.extend_from_slice(b"\r\n");
+
+        if content {
+            match framing {
+                Framing::Length(length) => {
+                    self.head.extend_from_slice(b"Content-Length: ");
+                    self.head.extend_from_slice(length.to_string().as_bytes());
+                    self.head.extend_from_slice(b"\r\n");
+                }
+                Framing::Chunked => self
+                    .head
+                    .extend_from_slice(b"Transfer-Encoding: chunked\r\n"),
+                Framing::Raw => {}
+            }
         }
-        for (head_name, head_content) in response.headers {
-            self.write_string(format!("{}: {}\r\n", head_name, head_content));
+        for (name, value) in response.headers.iter() {
+            if name.is_empty() || !name.bytes().all(is_tchar) {
+                continue;
+            }
+            // The length was sent along with the framing.
+            let replaced = content && matches!(framing, Framing::Length(_));
+            if (replaced || !length_allowed) && name.eq_ignore_ascii_case("Content-Length") {
+                continue;
+            }
+            self.head.extend_from_slice(name.as_bytes());
+            self.head.extend_from_slice(b": ");
+            push_sanitized(&mut self.head, value);
+            self.head.extend_from_slice(b"\r\n");
         }
-        self.write_str("\r\n");
-        self.write_str("\r\n");
+        self.head.extend_from_slice(b"\r\n");
 
-        self.write_str(&response.body);
-        if let Err(err) = self.flush() {
-            println!("{:?}", err);
+        match response.body {
+            Body::Bytes(bytes) if content => write_all_vectored(
+                &mut self.writer,
+                &mut [IoSlice::new(&self.head), IoSlice::new(&bytes)],
+            )?,
+            Body::Reader { reader, length } if content => {
+                self.writer.write_all(&self.head)?;
+                let mut reader = reader.take(length.unwrap_or(u64::MAX));
+                let mut out = BodyWriter::new(&mut self.writer, framing);
+                copy_chunks(&mut reader, &mut out, &mut self.chunk)?;
+                out.finish()?;
+            }
+            Body::Stream { write, .. } if content => {
+                self.writer.write_all(&self.head)?;
+                self.writer.flush()?;
+                let mut out = BodyWriter::new(&mut self.writer, framing);
+                write(&mut out)?;
+                out.finish()?;
+            }
+            _ => self.writer.write_all(&self.head)?,
         }
-        self.clear();
+        self.writer.flush()
     }
 }
 
-impl EspressoResponse {
-    pub fn new() -> EspressoResponse {
-        EspressoResponse {
-            status: 200,
-            message: "OK".to_string(),
-            body: "".to_string(),
-            headers: HashMap::new(),
+/// Copies `reader` to `out` through `buffer`, one chunk of at most [`CHUNK_SIZE`] at a time.
+fn copy_chunks(
+    reader: &mut impl Read,
+    out: &mut impl io::Write,
+    buffer: &mut Vec<u8>,
+) -> io::Result<()> {
+    buffer.resize(CHUNK_SIZE, 0);
+    loop {
+        match reader.read(buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => out.write_all(&buffer[..read])?,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
         }
     }
 }
 
-pub trait Serialize {
-    fn get(&self) -> &[u8];
+/// Whether a response with `status` may have content (RFC 9110 §6.4.1).
+fn allows_content(status: StatusCode) -> bool {
+    !status.is_informational()
+        && status != StatusCode::NO_CONTENT
+        && status != StatusCode::NOT_MODIFIED
+}
+
+/// Appends `value` without the bytes that would break the framing of the header section.
+fn push_sanitized(buffer: &mut Vec<u8>, value: &str) {
+    buffer.extend(
+        value
+            .bytes()
+            .filter(|byte| !matches!(byte, b'\r' | b'\n' | b'\0')),
+    );
 }
 
-impl Write for ResponseWriter {
-    fn write_bytes(&mut self, bytes: &[u8]) {
-        for byte in bytes.to_owned() {
-            self.buffer.push(byte);
+/// Writes every buffer in `bufs`, carrying on after partial writes.
+pub(crate) fn write_all_vectored(
+    writer: &mut impl io::Write,
+    mut bufs: &mut [IoSlice<'_>],
+) -> io::Result<()> {
+    IoSlice::advance_slices(&mut bufs, 0);
+    while !bufs.is_empty() {
+        match writer.write_vectored(bufs) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(wrote) => IoSlice::advance_slices(&mut bufs, wrote),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
         }
     }
+    Ok(())
+}
 
-    fn write_str(&mut self, str: &str) {
-        let bytes = str.bytes();
-        for byte in bytes {
-            self.buffer.push(byte);
-        }
+/// A value that can be sent as a response body, with the media type it is sent as.
+pub trait Serialize {
+    /// The media type of the serialized value, sent as `Content-Type`.
+    fn content_type(&self) -> &str;
+    fn serialize(&self) -> Body;
+}
+
+impl Serialize for str {
+    fn content_type(&self) -> &str {
+        "text/plain; charset=utf-8"
     }
 
-    fn write_string(&mut self, string: String) {
-        let bytes = string.bytes();
-        for byte in bytes {
-            self.buffer.push(byte);
-        }
+    fn serialize(&self) -> Body {
+        Body::from(self)
+    }
+}
+
+impl Serialize for [u8] {
+    fn content_type(&self) -> &str {
+        "application/octet-stream"
+    }
+
+    fn serialize(&self) -> Body {
+        Body::from(self)
     }
 }
 
-impl Serialize for ResponseWriter {
-    fn get(&self) -> &[u8] {
-        return &self.buffer;
+impl Serialize for json::JsonValue {
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    fn serialize(&self) -> Body {
+        Body::from(self.dump())
     }
 }
 
 #[derive(Debug)]
 pub enum EspressoResponseError {
     BufferError,
+    /// A status code outside 100 to 999.
+    InvalidStatusCode(usize),
     Unknown,
 }
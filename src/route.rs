@@ -0,0 +1,118 @@
+use crate::error::EspressoRouteError;
+
+/// A single piece of a route pattern, separated by `/`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Segment {
+    /// Matches the segment literally, e.g. `users`.
+    Static(String),
+    /// `:name`, matches exactly one segment.
+    Param(String),
+    /// `:name?`, matches one segment or nothing.
+    OptionalParam(String),
+    /// `*` or `*name`, matches the rest of the path. Only allowed as the last segment.
+    Wildcard(String),
+}
+
+/// A compiled Express-style route pattern such as `/users/:id`, `/posts/:slug?` or `/static/*path`.
+///
+/// Trailing and repeated slashes are ignored, so `/users/` matches the same routes as `/users`.
+#[derive(Clone, Debug)]
+pub struct RoutePattern {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl RoutePattern {
+    pub fn parse(pattern: &str) -> Result<RoutePattern, EspressoRouteError> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut names: Vec<&str> = Vec::new();
+        let parts: Vec<&str> = split_path(pattern).collect();
+
+        for (ind, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                let (name, optional) = match name.strip_suffix('?') {
+                    Some(name) => (name, true),
+                    None => (name, false),
+                };
+                if name.is_empty() {
+                    return Err(EspressoRouteError::InvalidPattern(format!(
+                        "Parameter without a name in {pattern}"
+                    )));
+                }
+                if names.contains(&name) {
+                    return Err(EspressoRouteError::InvalidPattern(format!(
+                        "Parameter {name} appears more than once in {pattern}"
+                    )));
+                }
+                names.push(name);
+                if optional {
+                    Segment::OptionalParam(name.to_string())
+                } else {
+                    Segment::Param(name.to_string())
+                }
+            } else if let Some(name) = part.strip_prefix('*') {
+                if ind != parts.len() - 1 {
+                    return Err(EspressoRouteError::InvalidPattern(format!(
+                        "Wildcard must be the last segment in {pattern}"
+                    )));
+                }
+                let name = if name.is_empty() { "*" } else { name };
+                if names.contains(&name) {
+                    return Err(EspressoRouteError::InvalidPattern(format!(
+                        "Parameter {name} appears more than once in {pattern}"
+                    )));
+                }
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(part.to_string())
+            };
+            segments.push(segment);
+        }
+
+        Ok(RoutePattern {
+            source: pattern.to_string(),
+            segments,
+        })
+    }
+
+    /// The pattern as it was registered.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+}
+
+/// Splits a path into its non-empty segments.
+pub(crate) fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|part| !part.is_empty())
+}
+
+/// Values captured from the request path by the named segments of a route pattern.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Params(Vec<(String, String)>);
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.0.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
@@ -0,0 +1,864 @@
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    error::{EspressoRouteError, HandlerResult, HttpError},
+    middleware::{
+        default_error_handler, error_from_fn, from_fn, EspressoErrorMiddleware, EspressoMiddleware,
+        Next,
+    },
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+    route::{split_path, Params, RoutePattern, Segment},
+    websocket::{self, WebSocket},
+};
+
+pub type RequestHandler = Box<
+    dyn Fn(&EspressoRequest, &mut EspressoResponse) -> Result<(), HttpError>
+        + Send
+        + Sync
+        + 'static,
+>;
+
+/// Holds the routes of an application in a prefix tree keyed by path segment.
+///
+/// Lookup follows the path down the tree one segment at a time. When several routes match the
+/// same path, static segments win over parameters, and parameters win over wildcards. A branch
+/// without a route for the method is left for the next one, so a lookup visits every node at most
+/// once: it costs as much as the length of the path, plus the routes that branch off along it and
+/// have to be tried and given up on.
+///
+/// Routes that would match exactly the same paths for the same method are rejected when they are
+/// registered, instead of leaving it to chance which of them runs.
+///
+/// Routers can be mounted under a prefix of another router with [`Router::mount`], to any depth.
+/// A mounted router matches its routes against the path with the prefix removed. Routes of the
+/// outer router win over those of a mounted router, except for its wildcard routes.
+///
+/// Middleware runs in the order it was registered. For a matched route, the middleware of the
+/// outer routers runs before that of the mounted routers, followed by the middleware registered
+/// with the route itself.
+///
+/// Errors returned by handlers and middleware go to the error middleware of the innermost router
+/// first and then outwards. Errors no error middleware deals with are answered with their status.
+#[derive(Clone, Default)]
+pub struct Router {
+    root: Node,
+    /// Middleware with the static prefix it applies to, empty for middleware applying to every request.
+    middleware: Vec<(Vec<String>, Arc<EspressoMiddleware>)>,
+    error_middleware: Vec<Arc<EspressoErrorMiddleware>>,
+}
+
+/// The outcome of looking up a request in a [`Router`]. It holds on to what it found rather than
+/// borrowing the router, so it can be handed to another thread along with its request.
+pub enum RouteMatch {
+    Found {
+        handler: Arc<RequestHandler>,
+        /// The pattern the route was registered with.
+        pattern: String,
+        params: Params,
+        /// The part of the path consumed by the prefixes of mounted routers, empty when the route
+        /// belongs to the router itself.
+        base_url: String,
+        /// The middleware to run before the handler, in order.
+        middleware: Vec<Arc<EspressoMiddleware>>,
+        /// The error middleware to run if the handler or middleware fail, in order.
+        error_middleware: Vec<Arc<EspressoErrorMiddleware>>,
+        /// Whether the route reads the request body itself, see [`Router::route_streaming`].
+        stream_body: bool,
+    },
+    /// The path exists, but not for the requested method. Holds the methods that are registered.
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+impl RouteMatch {
+    /// Whether the request goes to a route that reads the request body itself.
+    pub(crate) fn streams_body(&self) -> bool {
+        matches!(
+            self,
+            RouteMatch::Found {
+                stream_body: true,
+                ..
+            }
+        )
+    }
+}
+
+#[derive(Clone, Default)]
+struct Node {
+    statics: HashMap<String, Node>,
+    param: Option<Box<Node>>,
+    wildcard: Option<Box<Endpoint>>,
+    endpoint: Option<Endpoint>,
+    /// A router mounted at the path leading to this node.
+    mount: Option<Box<Router>>,
+}
+
+/// The handlers registered for one path shape.
+#[derive(Clone, Default)]
+struct Endpoint {
+    handlers: HashMap<RequestMethod, Route>,
+    /// Handler registered through `all`, used for methods without a handler of their own.
+    fallback: Option<Route>,
+}
+
+#[derive(Clone)]
+struct Route {
+    pattern: RoutePattern,
+    /// Names of the parameters captured along this shape of the pattern, in path order.
+    names: Vec<String>,
+    handler: Arc<RequestHandler>,
+    middleware: Vec<Arc<EspressoMiddleware>>,
+    stream_body: bool,
+}
+
+impl Route {
+    fn params(&self, values: &[&str]) -> Params {
+        let mut params = Params::default();
+        for (name, value) in self.names.iter().zip(values) {
+            params.insert(name, value);
+        }
+        params
+    }
+}
+
+impl Endpoint {
+    /// The route handling `method`. HEAD requests without a handler of their own go to the GET handler.
+    fn route_for(&self, method: &RequestMethod) -> Option<&Route> {
+        let head_as_get = match method {
+            RequestMethod::HEAD => self.handlers.get(&RequestMethod::GET),
+            _ => None,
+        };
+        self.handlers
+            .get(method)
+            .or(head_as_get)
+            .or(self.fallback.as_ref())
+    }
+
+    /// The methods the path answers, counting those answered automatically.
+    fn allowed_methods(&self) -> Vec<&str> {
+        let mut allowed: Vec<&str> = self.handlers.keys().map(RequestMethod::as_str).collect();
+        if self.handlers.contains_key(&RequestMethod::GET) {
+            allowed.push(RequestMethod::HEAD.as_str());
+        }
+        allowed.push(RequestMethod::OPTIONS.as_str());
+        allowed
+    }
+
+    /// The route already occupying the slot `method` would be registered in, if any.
+    fn existing(&self, method: Option<&RequestMethod>) -> Option<&Route> {
+        match method {
+            Some(method) => self.handlers.get(method),
+            None => self.fallback.as_ref(),
+        }
+    }
+
+    fn insert(&mut self, method: Option<RequestMethod>, route: Route) {
+        match method {
+            Some(method) => {
+                self.handlers.insert(method, route);
+            }
+            None => self.fallback = Some(route),
+        }
+    }
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Registers `handler` for `method` requests matching `pattern`.
+    ///
+    /// Patterns follow Express: `:name` captures a segment, `:name?` captures an optional segment and
+    /// a trailing `*` or `*name` captures the rest of the path.
+    pub fn try_route<R: HandlerResult>(
+        &mut self,
+        method: RequestMethod,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) -> Result<(), EspressoRouteError> {
+        self.insert(
+            Some(method),
+            pattern,
+            Vec::new(),
+            false,
+            Box::new(move |request, response| handler(request, response).into_result()),
+        )
+    }
+
+    /// Same as [`Router::try_route`], except that the request body isn't read before `handler`
+    /// runs. The handler reads it from the connection with [`EspressoRequest::body_reader`], so
+    /// bodies of any size can be handled without holding them in memory.
+    pub fn try_route_streaming<R: HandlerResult>(
+        &mut self,
+        method: RequestMethod,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) -> Result<(), EspressoRouteError> {
+        self.insert(
+            Some(method),
+            pattern,
+            Vec::new(),
+            true,
+            Box::new(move |request, response| handler(request, response).into_result()),
+        )
+    }
+
+    /// Same as [`Router::try_route`], with `middleware` running only for this route.
+    pub fn try_route_with<R: HandlerResult>(
+        &mut self,
+        method: RequestMethod,
+        pattern: &str,
+        middleware: Vec<EspressoMiddleware>,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) -> Result<(), EspressoRouteError> {
+        self.insert(
+            Some(method),
+            pattern,
+            middleware,
+            false,
+            Box::new(move |request, response| handler(request, response).into_result()),
+        )
+    }
+
+    /// Registers `handler` for every request method matching `pattern` that has no handler of its own.
+    pub fn try_all<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) -> Result<(), EspressoRouteError> {
+        self.insert(
+            None,
+            pattern,
+            Vec::new(),
+            false,
+            Box::new(move |request, response| handler(request, response).into_result()),
+        )
+    }
+
+    /// Same as [`Router::try_all`].
+    ///
+    /// # Panics
+    /// If `pattern` is invalid or conflicts with an existing route.
+    pub fn all<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        if let Err(err) = self.try_all(pattern, handler) {
+            panic!("Unable to register route: {err:?}");
+        }
+    }
+
+    /// Same as [`Router::try_route`].
+    ///
+    /// # Panics
+    /// If `pattern` is invalid or conflicts with an existing route.
+    pub fn route<R: HandlerResult>(
+        &mut self,
+        method: RequestMethod,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        if let Err(err) = self.try_route(method, pattern, handler) {
+            panic!("Unable to register route: {err:?}");
+        }
+    }
+
+    /// Same as [`Router::try_route_streaming`].
+    ///
+    /// # Panics
+    /// If `pattern` is invalid or conflicts with an existing route.
+    pub fn route_streaming<R: HandlerResult>(
+        &mut self,
+        method: RequestMethod,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        if let Err(err) = self.try_route_streaming(method, pattern, handler) {
+            panic!("Unable to register route: {err:?}");
+        }
+    }
+
+    /// Same as [`Router::try_route_with`].
+    ///
+    /// # Panics
+    /// If `pattern` is invalid or conflicts with an existing route.
+    pub fn route_with<R: HandlerResult>(
+        &mut self,
+        method: RequestMethod,
+        pattern: &str,
+        middleware: Vec<EspressoMiddleware>,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        if let Err(err) = self.try_route_with(method, pattern, middleware, handler) {
+            panic!("Unable to register route: {err:?}");
+        }
+    }
+
+    pub fn get<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.route(RequestMethod::GET, pattern, handler);
+    }
+
+    pub fn post<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.route(RequestMethod::POST, pattern, handler);
+    }
+
+    pub fn put<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.route(RequestMethod::PUT, pattern, handler);
+    }
+
+    pub fn patch<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.route(RequestMethod::PATCH, pattern, handler);
+    }
+
+    pub fn delete<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.route(RequestMethod::DELETE, pattern, handler);
+    }
+
+    pub fn options<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.route(RequestMethod::OPTIONS, pattern, handler);
+    }
+
+    pub fn head<R: HandlerResult>(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, &mut EspressoResponse) -> R + Send + Sync + 'static,
+    ) {
+        self.route(RequestMethod::HEAD, pattern, handler);
+    }
+
+    /// Adds a WebSocket endpoint: GET requests to `pattern` are upgraded with
+    /// [`websocket::accept`] and `handler` gets the connection once the handshake is sent.
+    /// Middleware runs before the upgrade, as it does for any other request.
+    pub fn ws(
+        &mut self,
+        pattern: &str,
+        handler: impl Fn(&EspressoRequest, WebSocket) + Send + Sync + 'static,
+    ) {
+        let handler = Arc::new(handler);
+        self.get(
+            pattern,
+            move |request: &EspressoRequest, response: &mut EspressoResponse| {
+                let handler = Arc::clone(&handler);
+                websocket::accept(request, response, move |request, socket| {
+                    handler(request, socket)
+                })
+            },
+        );
+    }
+
+    /// Adds middleware that runs for every request handled by this router, including requests
+    /// that match no route.
+    pub fn middleware<R: HandlerResult>(
+        &mut self,
+        middleware: impl Fn(&mut EspressoRequest, &mut EspressoResponse, Next) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.middleware
+            .push((Vec::new(), Arc::new(from_fn(middleware))));
+    }
+
+    /// Adds middleware that runs for requests whose path starts with `prefix`, which may only contain
+    /// static segments. `/admin` applies to `/admin` and `/admin/users`, but not to `/administrators`.
+    pub fn try_middleware_at<R: HandlerResult>(
+        &mut self,
+        prefix: &str,
+        middleware: impl Fn(&mut EspressoRequest, &mut EspressoResponse, Next) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) -> Result<(), EspressoRouteError> {
+        let prefix = static_segments(prefix)?;
+        self.middleware
+            .push((prefix, Arc::new(from_fn(middleware))));
+        Ok(())
+    }
+
+    /// Same as [`Router::try_middleware_at`].
+    ///
+    /// # Panics
+    /// If `prefix` is invalid.
+    pub fn middleware_at<R: HandlerResult>(
+        &mut self,
+        prefix: &str,
+        middleware: impl Fn(&mut EspressoRequest, &mut EspressoResponse, Next) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        if let Err(err) = self.try_middleware_at(prefix, middleware) {
+            panic!("Unable to register middleware: {err:?}");
+        }
+    }
+
+    /// Adds error middleware, which runs when a handler or middleware of this router, or of a router
+    /// mounted in it, returns an error.
+    pub fn error_middleware<R: HandlerResult>(
+        &mut self,
+        middleware: impl Fn(HttpError, &EspressoRequest, &mut EspressoResponse) -> R
+            + Send
+            + Sync
+            + 'static,
+    ) {
+        self.error_middleware
+            .push(Arc::new(error_from_fn(middleware)));
+    }
+
+    /// The middleware of this router that applies to `parts`, the path relative to the router.
+    fn middleware_for(&self, parts: &[&str], chain: &mut Vec<Arc<EspressoMiddleware>>) {
+        for (prefix, middleware) in &self.middleware {
+            if parts.len() >= prefix.len() && prefix.iter().zip(parts).all(|(a, b)| a == b) {
+                chain.push(Arc::clone(middleware));
+            }
+        }
+    }
+
+    /// Mounts `router` under `prefix`, so that a request for `{prefix}/users` is matched against
+    /// `/users` in `router`. The prefix may only contain static segments.
+    pub fn try_mount(&mut self, prefix: &str, router: Router) -> Result<(), EspressoRouteError> {
+        let mut node = &mut self.root;
+        for segment in static_segments(prefix)? {
+            node = node.statics.entry(segment).or_default();
+        }
+        if node.mount.is_some() {
+            return Err(EspressoRouteError::Conflict(format!(
+                "A router is already mounted at {prefix}"
+            )));
+        }
+        node.mount = Some(Box::new(router));
+        Ok(())
+    }
+
+    /// Same as [`Router::try_mount`].
+    ///
+    /// # Panics
+    /// If `prefix` is invalid or already has a router mounted.
+    pub fn mount(&mut self, prefix: &str, router: Router) {
+        if let Err(err) = self.try_mount(prefix, router) {
+            panic!("Unable to mount router: {err:?}");
+        }
+    }
+
+    fn insert(
+        &mut self,
+        method: Option<RequestMethod>,
+        pattern: &str,
+        middleware: Vec<EspressoMiddleware>,
+        stream_body: bool,
+        handler: RequestHandler,
+    ) -> Result<(), EspressoRouteError> {
+        let pattern = RoutePattern::parse(pattern)?;
+        // An optional parameter is registered as two shapes: with and without its segment.
+        let shapes = expand_optionals(pattern.segments());
+
+        // Check every shape before inserting any, so a failed registration leaves the tree untouched.
+        for shape in &shapes {
+            let existing = self
+                .root
+                .endpoint_for(shape)
+                .and_then(|endpoint| endpoint.existing(method.as_ref()));
+            if let Some(existing) = existing {
+                let method = method.as_ref().map_or("ALL", RequestMethod::as_str);
+                return Err(EspressoRouteError::Conflict(format!(
+                    "{method} {} conflicts with {method} {}",
+                    pattern.as_str(),
+                    existing.pattern.as_str()
+                )));
+            }
+        }
+
+        let handler = Arc::new(handler);
+        let middleware: Vec<Arc<EspressoMiddleware>> =
+            middleware.into_iter().map(Arc::new).collect();
+        for shape in &shapes {
+            let names = shape
+                .iter()
+                .filter_map(|segment| match segment {
+                    Segment::Static(_) => None,
+                    Segment::Param(name)
+                    | Segment::OptionalParam(name)
+                    | Segment::Wildcard(name) => Some(name.clone()),
+                })
+                .collect();
+            let route = Route {
+                pattern: pattern.clone(),
+                names,
+                handler: Arc::clone(&handler),
+                middleware: middleware.clone(),
+                stream_body,
+            };
+            self.root
+                .endpoint_for_mut(shape)
+                .insert(method.clone(), route);
+        }
+        Ok(())
+    }
+
+    /// Finds the handler for `method` and `path`, following the precedence described on [`Router`].
+    pub fn find(&self, method: &RequestMethod, path: &str) -> RouteMatch {
+        let parts: Vec<&str> = split_path(path).collect();
+
+        let mut found: Option<RouteMatch> = None;
+        // Without a match, every endpoint matching the path has been visited, which makes them
+        // the ones listed in `Allow`.
+        let mut allowed: Vec<String> = Vec::new();
+        self.root.visit(
+            &parts,
+            0,
+            &mut vec![(self, 0)],
+            &mut Vec::new(),
+            &mut |endpoint, values, trail| {
+                let Some(route) = endpoint.route_for(method) else {
+                    allowed.extend(endpoint.allowed_methods().into_iter().map(String::from));
+                    return false;
+                };
+                let mut middleware = Vec::new();
+                for (router, depth) in trail {
+                    router.middleware_for(&parts[*depth..], &mut middleware);
+                }
+                middleware.extend(route.middleware.iter().cloned());
+                let error_middleware = trail
+                    .iter()
+                    .rev()
+                    .flat_map(|(router, _)| router.error_middleware.iter().cloned())
+                    .collect();
+
+                // The mount prefix is cut from the original path, so it keeps any repeated slashes.
+                let mounted = trail.last().map_or(0, |(_, depth)| *depth);
+                let base_url = match mounted.checked_sub(1).map(|last| parts[last]) {
+                    Some(last) => {
+                        let end = last.as_ptr() as usize - path.as_ptr() as usize + last.len();
+                        path[..end].to_string()
+                    }
+                    None => String::new(),
+                };
+                found = Some(RouteMatch::Found {
+                    handler: Arc::clone(&route.handler),
+                    pattern: route.pattern.as_str().to_string(),
+                    params: route.params(values),
+                    base_url,
+                    middleware,
+                    error_middleware,
+                    stream_body: route.stream_body,
+                });
+                true
+            },
+        );
+        if let Some(found) = found {
+            return found;
+        }
+        if allowed.is_empty() {
+            return RouteMatch::NotFound;
+        }
+        allowed.sort_unstable();
+        allowed.dedup();
+        RouteMatch::MethodNotAllowed(allowed)
+    }
+
+    /// Looks up the route for `request`, to be handed to [`Router::handle_match`].
+    pub(crate) fn lookup(&self, request: &EspressoRequest) -> RouteMatch {
+        // The asterisk form of OPTIONS is the only target that isn't a path, and matches no route.
+        match request.full_path() {
+            "*" => RouteMatch::NotFound,
+            path => self.find(&request.method, path),
+        }
+    }
+
+    /// Runs the middleware and handler matching the request.
+    ///
+    /// Requests without a matching route still go through the middleware of this router before
+    /// being answered with `404 Not Found` or `405 Method Not Allowed`. `OPTIONS` requests without
+    /// a handler are answered with an `Allow` header listing the methods of the path instead.
+    pub fn handle(&self, request: &mut EspressoRequest, response: &mut EspressoResponse) {
+        let found = self.lookup(request);
+        self.handle_match(found, request, response);
+    }
+
+    /// Same as [`Router::handle`], with the route of the request already looked up.
+    pub(crate) fn handle_match(
+        &self,
+        found: RouteMatch,
+        request: &mut EspressoRequest,
+        response: &mut EspressoResponse,
+    ) {
+        let (result, error_middleware) = match found {
+            RouteMatch::Found {
+                handler,
+                pattern,
+                params,
+                base_url,
+                middleware,
+                error_middleware,
+                ..
+            } => {
+                request.route = Some(pattern);
+                request.params = params;
+                request.base_url = base_url;
+                let result =
+                    Next::new(&middleware, &|request, response| handler(request, response))
+                        .run(request, response);
+                (result, error_middleware)
+            }
+            RouteMatch::MethodNotAllowed(allowed) => {
+                let allowed = allowed.join(", ");
+                // Without a handler of its own, OPTIONS is answered with the methods of the path.
+                let status = match request.method {
+                    RequestMethod::OPTIONS => 200,
+                    _ => 405,
+                };
+                let result = self.run_unrouted(request, response, &|_, response| {
+                    response.status(status);
+                    response.set_header("Allow", &allowed);
+                    Ok(())
+                });
+                (result, self.error_middleware.iter().cloned().collect())
+            }
+            // `OPTIONS *` asks about the server rather than a path (RFC 9110 §9.3.7), so there is
+            // nothing to list.
+            RouteMatch::NotFound
+                if request.method == RequestMethod::OPTIONS && request.full_path() == "*" =>
+            {
+                let result = self.run_unrouted(request, response, &|_, _| Ok(()));
+                (result, self.error_middleware.iter().cloned().collect())
+            }
+            RouteMatch::NotFound => {
+                let result = self.run_unrouted(request, response, &|_, response| {
+                    response.status(404);
+                    Ok(())
+                });
+                (result, self.error_middleware.iter().cloned().collect())
+            }
+        };
+
+        let mut result = result;
+        for middleware in error_middleware {
+            match result {
+                Ok(()) => return,
+                Err(err) => result = middleware(err, request, response),
+            }
+        }
+        if let Err(err) = result {
+            default_error_handler(err, request, response);
+        }
+    }
+
+    fn run_unrouted(
+        &self,
+        request: &mut EspressoRequest,
+        response: &mut EspressoResponse,
+        endpoint: &dyn Fn(&mut EspressoRequest, &mut EspressoResponse) -> Result<(), HttpError>,
+    ) -> Result<(), HttpError> {
+        let path = request.normalized_path.clone();
+        let parts: Vec<&str> = split_path(&path).collect();
+        let mut middleware = Vec::new();
+        self.middleware_for(&parts, &mut middleware);
+        Next::new(&middleware, endpoint).run(request, response)
+    }
+}
+
+/// Parses a prefix made only of static segments, as used for mounting routers and scoping middleware.
+fn static_segments(prefix: &str) -> Result<Vec<String>, EspressoRouteError> {
+    let pattern = RoutePattern::parse(prefix)?;
+    pattern
+        .segments()
+        .iter()
+        .map(|segment| match segment {
+            Segment::Static(value) => Ok(value.clone()),
+            _ => Err(EspressoRouteError::InvalidPattern(format!(
+                "Prefix {prefix} may only contain static segments"
+            ))),
+        })
+        .collect()
+}
+
+/// Lists the segment shapes a pattern matches, with every combination of optional parameters
+/// present or left out. Shapes where earlier optional parameters are present come first, and a
+/// shape that is indistinguishable from an earlier one is dropped, so `/a/:x?/:y?` gives `/a/1`
+/// to `x`.
+fn expand_optionals(segments: &[Segment]) -> Vec<Vec<&Segment>> {
+    let mut shapes: Vec<Vec<&Segment>> = vec![Vec::new()];
+    for segment in segments {
+        shapes = if let Segment::OptionalParam(_) = segment {
+            shapes
+                .into_iter()
+                .flat_map(|shape| {
+                    let mut with = shape.clone();
+                    with.push(segment);
+                    [with, shape]
+                })
+                .collect()
+        } else {
+            shapes
+                .into_iter()
+                .map(|mut shape| {
+                    shape.push(segment);
+                    shape
+                })
+                .collect()
+        };
+    }
+
+    let mut unique: Vec<Vec<&Segment>> = Vec::new();
+    for shape in shapes {
+        if !unique.iter().any(|existing| same_shape(existing, &shape)) {
+            unique.push(shape);
+        }
+    }
+    unique
+}
+
+/// Whether two shapes match exactly the same paths, ignoring parameter names.
+fn same_shape(a: &[&Segment], b: &[&Segment]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).all(|(a, b)| match (a, b) {
+            (Segment::Static(a), Segment::Static(b)) => a == b,
+            (Segment::Wildcard(_), Segment::Wildcard(_)) => true,
+            (
+                Segment::Param(_) | Segment::OptionalParam(_),
+                Segment::Param(_) | Segment::OptionalParam(_),
+            ) => true,
+            _ => false,
+        })
+}
+
+/// Called for each endpoint found during a lookup, with the values captured on the way there and
+/// the routers entered along with the depth they were mounted at.
+type Visitor<'a, 'n> = dyn FnMut(&'n Endpoint, &[&str], &[(&'n Router, usize)]) -> bool + 'a;
+
+impl Node {
+    fn endpoint_for(&self, shape: &[&Segment]) -> Option<&Endpoint> {
+        let Some((segment, rest)) = shape.split_first() else {
+            return self.endpoint.as_ref();
+        };
+        match segment {
+            Segment::Static(value) => self.statics.get(value)?.endpoint_for(rest),
+            Segment::Param(_) | Segment::OptionalParam(_) => {
+                self.param.as_ref()?.endpoint_for(rest)
+            }
+            Segment::Wildcard(_) => self.wildcard.as_deref(),
+        }
+    }
+
+    fn endpoint_for_mut(&mut self, shape: &[&Segment]) -> &mut Endpoint {
+        let Some((segment, rest)) = shape.split_first() else {
+            return self.endpoint.get_or_insert_with(Endpoint::default);
+        };
+        match segment {
+            Segment::Static(value) => self
+                .statics
+                .entry(value.clone())
+                .or_default()
+                .endpoint_for_mut(rest),
+            Segment::Param(_) | Segment::OptionalParam(_) => self
+                .param
+                .get_or_insert_with(Box::default)
+                .endpoint_for_mut(rest),
+            Segment::Wildcard(_) => self.wildcard.get_or_insert_with(Box::default),
+        }
+    }
+
+    /// Calls `accept` on every endpoint matching `parts`, in order of precedence, until it returns `true`.
+    /// `values` holds the segments captured by parameters and wildcards on the way to the endpoint.
+    /// `depth` counts the segments of the full path consumed before reaching this node, and `trail`
+    /// lists the routers entered so far with the depth they were mounted at.
+    fn visit<'n, 'p>(
+        &'n self,
+        parts: &[&'p str],
+        depth: usize,
+        trail: &mut Vec<(&'n Router, usize)>,
+        values: &mut Vec<&'p str>,
+        accept: &mut Visitor<'_, 'n>,
+    ) -> bool {
+        let Some((part, rest)) = parts.split_first() else {
+            if let Some(endpoint) = &self.endpoint {
+                if accept(endpoint, values, trail) {
+                    return true;
+                }
+            }
+            return self.visit_mount(parts, depth, trail, accept)
+                || self.visit_wildcard(parts, trail, values, accept);
+        };
+
+        if let Some(child) = self.statics.get(*part) {
+            if child.visit(rest, depth + 1, trail, values, accept) {
+                return true;
+            }
+        }
+        if let Some(child) = &self.param {
+            values.push(part);
+            if child.visit(rest, depth + 1, trail, values, accept) {
+                return true;
+            }
+            values.pop();
+        }
+        self.visit_mount(parts, depth, trail, accept)
+            || self.visit_wildcard(parts, trail, values, accept)
+    }
+
+    /// Hands the rest of the path to the router mounted at this node. Its routes have their own
+    /// parameter names, so it starts with no captured values.
+    fn visit_mount<'n>(
+        &'n self,
+        parts: &[&str],
+        depth: usize,
+        trail: &mut Vec<(&'n Router, usize)>,
+        accept: &mut Visitor<'_, 'n>,
+    ) -> bool {
+        let Some(router) = &self.mount else {
+            return false;
+        };
+        trail.push((router, depth));
+        if router
+            .root
+            .visit(parts, depth, trail, &mut Vec::new(), accept)
+        {
+            return true;
+        }
+        trail.pop();
+        false
+    }
+
+    fn visit_wildcard<'n>(
+        &'n self,
+        parts: &[&str],
+        trail: &[(&'n Router, usize)],
+        values: &[&str],
+        accept: &mut Visitor<'_, 'n>,
+    ) -> bool {
+        let Some(endpoint) = &self.wildcard else {
+            return false;
+        };
+        let rest = parts.join("/");
+        let mut captured: Vec<&str> = values.to_vec();
+        captured.push(&rest);
+        accept(endpoint, &captured, trail)
+    }
+}
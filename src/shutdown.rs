@@ -0,0 +1,294 @@
+use std::{
+    collections::HashMap,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::request::lock;
+
+/// Stops an [`crate::espresso::Espresso`] server, from any thread.
+///
+/// Shutting down stops accepting connections and closes the idle ones. Requests in flight are
+/// answered with `Connection: close`, for up to [`crate::config::EspressoConfig::shutdown_timeout`],
+/// after which the connections still open are closed. The server then stops its workers.
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    state: Arc<ShutdownState>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(state: Arc<ShutdownState>) -> ShutdownHandle {
+        ShutdownHandle { state }
+    }
+
+    /// Starts shutting the server down, without waiting for it to stop. Calling it again has no
+    /// effect.
+    pub fn shutdown(&self) {
+        self.state.shutdown();
+    }
+
+    /// Whether the server is shutting down or has stopped.
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.is_stopping()
+    }
+
+    /// Waits until the server has shut down and its workers have stopped.
+    pub fn wait(&self) {
+        let mut connections = lock(&self.state.connections);
+        while !connections.stopped {
+            connections = self
+                .state
+                .changed
+                .wait(connections)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Shuts the server down when the process receives `SIGTERM` or `SIGINT`. Once one has been
+    /// received, they are back to ending the process, so a second one stops it right away.
+    #[cfg(unix)]
+    pub fn shutdown_on_signals(&self) -> std::io::Result<()> {
+        signals::watch(self.clone())
+    }
+}
+
+/// What the accept loop, the connections and the [`ShutdownHandle`]s of a server share.
+pub(crate) struct ShutdownState {
+    stopping: AtomicBool,
+    /// Where to connect to wake the accept loop up.
+    wake_addr: Option<SocketAddr>,
+    connections: Mutex<Connections>,
+    /// Signalled when a connection is closed, when shutting down starts, and once the server has
+    /// stopped.
+    changed: Condvar,
+}
+
+struct Connections {
+    next_id: u64,
+    open: HashMap<u64, Arc<Connection>>,
+    stopped: bool,
+}
+
+struct Connection {
+    tcp: TcpStream,
+    /// Whether the connection is waiting for its next request.
+    idle: AtomicBool,
+}
+
+impl ShutdownState {
+    pub(crate) fn new(listening_on: Option<SocketAddr>) -> ShutdownState {
+        let wake_addr = listening_on.map(|mut addr| {
+            // Connections to the unspecified address don't go anywhere, loopback does.
+            if addr.ip().is_unspecified() {
+                addr.set_ip(match addr {
+                    SocketAddr::V4(_) => [127, 0, 0, 1].into(),
+                    SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+                });
+            }
+            addr
+        });
+        ShutdownState {
+            stopping: AtomicBool::new(false),
+            wake_addr,
+            connections: Mutex::new(Connections {
+                next_id: 0,
+                open: HashMap::new(),
+                stopped: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub(crate) fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    fn shutdown(&self) {
+        if self.stopping.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        for connection in lock(&self.connections).open.values() {
+            if connection.idle.load(Ordering::SeqCst) {
+                // Ends the wait for the next request as if the client had closed the connection.
+                let _ = connection.tcp.shutdown(Shutdown::Read);
+            }
+        }
+        self.changed.notify_all();
+        // The accept loop only sees the flag once a connection comes in.
+        if let Some(addr) = self.wake_addr {
+            let _ = TcpStream::connect_timeout(&addr, Duration::from_secs(1));
+        }
+    }
+
+    /// Waits until the server starts shutting down.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn wait_for_shutdown(&self) {
+        let mut connections = lock(&self.connections);
+        while !self.is_stopping() {
+            connections = self
+                .changed
+                .wait(connections)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+    }
+
+    /// Keeps track of `tcp` until the returned guard is dropped, so shutting down can close it.
+    pub(crate) fn track(self: &Arc<Self>, tcp: &TcpStream) -> Option<TrackedConnection> {
+        let connection = Arc::new(Connection {
+            tcp: tcp.try_clone().ok()?,
+            idle: AtomicBool::new(false),
+        });
+        let mut connections = lock(&self.connections);
+        let id = connections.next_id;
+        connections.next_id += 1;
+        connections.open.insert(id, Arc::clone(&connection));
+        Some(TrackedConnection {
+            state: Arc::clone(self),
+            id,
+            connection,
+        })
+    }
+
+    /// Waits for the open connections to close until `deadline`, then closes the ones left.
+    pub(crate) fn drain(&self, deadline: Instant) {
+        let mut connections = lock(&self.connections);
+        while !connections.open.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                for connection in connections.open.values() {
+                    let _ = connection.tcp.shutdown(Shutdown::Both);
+                }
+                return;
+            }
+            connections = self
+                .changed
+                .wait_timeout(connections, deadline - now)
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .0;
+        }
+    }
+
+    /// Marks the server as stopped, waking up [`ShutdownHandle::wait`].
+    pub(crate) fn stopped(&self) {
+        lock(&self.connections).stopped = true;
+        self.changed.notify_all();
+    }
+}
+
+/// A connection the server is serving, until it is dropped.
+pub(crate) struct TrackedConnection {
+    state: Arc<ShutdownState>,
+    id: u64,
+    connection: Arc<Connection>,
+}
+
+impl TrackedConnection {
+    /// Marks the connection as waiting for its next request. Returns `false` if the server is
+    /// shutting down, so the connection should be closed instead.
+    pub(crate) fn idle(&self) -> bool {
+        self.connection.idle.store(true, Ordering::SeqCst);
+        !self.state.is_stopping()
+    }
+
+    /// Marks the connection as busy with a request, which shutting down lets finish.
+    pub(crate) fn busy(&self) {
+        self.connection.idle.store(false, Ordering::SeqCst);
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        lock(&self.state.connections).open.remove(&self.id);
+        self.state.changed.notify_all();
+    }
+}
+
+#[cfg(unix)]
+mod signals {
+    use std::{
+        io,
+        sync::{
+            atomic::{AtomicI32, Ordering},
+            Mutex, OnceLock,
+        },
+        thread,
+    };
+
+    use super::ShutdownHandle;
+    use crate::request::lock;
+
+    /// The write end of the pipe the signal handler wakes the watcher thread through.
+    static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+    /// The servers to shut down on a signal, once the watcher thread is running.
+    static HANDLES: OnceLock<Mutex<Vec<ShutdownHandle>>> = OnceLock::new();
+
+    extern "C" fn on_signal(_: libc::c_int) {
+        // Only async-signal-safe calls here: the watcher thread does the rest.
+        let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+        if fd >= 0 {
+            unsafe { libc::write(fd, [1u8].as_ptr().cast(), 1) };
+        }
+    }
+
+    pub(super) fn watch(handle: ShutdownHandle) -> io::Result<()> {
+        if let Some(handles) = HANDLES.get() {
+            lock(handles).push(handle);
+            return Ok(());
+        }
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let [read_fd, write_fd] = fds;
+        if HANDLES.set(Mutex::new(vec![handle.clone()])).is_err() {
+            // Another thread started watching first.
+            unsafe {
+                libc::close(read_fd);
+                libc::close(write_fd);
+            }
+            return watch(handle);
+        }
+        SIGNAL_PIPE.store(write_fd, Ordering::Relaxed);
+        set_handler(on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t)?;
+
+        thread::spawn(move || {
+            let mut byte = 0u8;
+            loop {
+                let read = unsafe { libc::read(read_fd, (&mut byte as *mut u8).cast(), 1) };
+                if read == 1 {
+                    break;
+                }
+                if read < 0 && io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return;
+            }
+            let _ = set_handler(libc::SIG_DFL);
+            if let Some(handles) = HANDLES.get() {
+                for handle in lock(handles).iter() {
+                    handle.shutdown();
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn set_handler(handler: libc::sighandler_t) -> io::Result<()> {
+        for signal in [libc::SIGTERM, libc::SIGINT] {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = handler;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,149 @@
+use std::{
+    io::{self, Write},
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+/// One message of an event stream: a `data` payload with an optional event type, id and
+/// reconnection time.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// An unnamed event carrying `data`, which may span several lines.
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the event type, which browsers dispatch the message under instead of `message`.
+    pub fn event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(event.into());
+        self
+    }
+
+    /// Sets the id the client sends back in `Last-Event-ID` when it reconnects.
+    pub fn id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Sets how long the client waits before reconnecting once the stream ends.
+    pub fn retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Appends the event in the `text/event-stream` format. Line breaks in the type and id,
+    /// which would end their field early, are left out; every line of the data gets a field.
+    fn write_to(&self, buffer: &mut String) {
+        if let Some(event) = &self.event {
+            push_field(buffer, "event", event);
+        }
+        for line in self
+            .data
+            .split("\r\n")
+            .flat_map(|line| line.split(['\r', '\n']))
+        {
+            push_field(buffer, "data", line);
+        }
+        if let Some(id) = &self.id {
+            // An id with NUL is ignored by clients, so it is dropped too.
+            push_field(buffer, "id", &id.replace('\0', ""));
+        }
+        if let Some(retry) = self.retry {
+            push_field(buffer, "retry", &retry.as_millis().to_string());
+        }
+        buffer.push('\n');
+    }
+}
+
+fn push_field(buffer: &mut String, name: &str, value: &str) {
+    buffer.push_str(name);
+    buffer.push_str(": ");
+    buffer.extend(value.chars().filter(|c| !matches!(c, '\r' | '\n')));
+    buffer.push('\n');
+}
+
+/// Sends the events of a `text/event-stream` response. See [`crate::response::EspressoResponse::sse`].
+///
+/// Every message is sent as soon as it is written. Once the client has gone away, writes fail,
+/// which is how a disconnect shows up; [`SseSender::comment`] lets an idle stream find out.
+pub struct SseSender<'a> {
+    out: &'a mut dyn Write,
+    last_event_id: Option<String>,
+    buffer: String,
+}
+
+impl<'a> SseSender<'a> {
+    pub(crate) fn new(out: &'a mut dyn Write, last_event_id: Option<String>) -> SseSender<'a> {
+        SseSender {
+            out,
+            last_event_id,
+            buffer: String::new(),
+        }
+    }
+
+    /// The `Last-Event-ID` the client reconnected with: the id of the last event it received,
+    /// to resume the stream after.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    pub fn send(&mut self, event: &Event) -> io::Result<()> {
+        self.buffer.clear();
+        event.write_to(&mut self.buffer);
+        self.flush_buffer()
+    }
+
+    /// Sends an unnamed event carrying `data`.
+    pub fn data(&mut self, data: &str) -> io::Result<()> {
+        self.send(&Event::new(data))
+    }
+
+    /// Sets how long the client waits before reconnecting once the stream ends.
+    pub fn retry(&mut self, retry: Duration) -> io::Result<()> {
+        self.buffer.clear();
+        push_field(&mut self.buffer, "retry", &retry.as_millis().to_string());
+        self.buffer.push('\n');
+        self.flush_buffer()
+    }
+
+    /// Sends a comment, which clients ignore. Sent periodically, it keeps proxies from closing an
+    /// idle stream and notices a client that has gone away.
+    pub fn comment(&mut self, text: &str) -> io::Result<()> {
+        self.buffer.clear();
+        for line in text.split("\r\n").flat_map(|line| line.split(['\r', '\n'])) {
+            self.buffer.push_str(": ");
+            self.buffer.push_str(line);
+            self.buffer.push('\n');
+        }
+        self.buffer.push('\n');
+        self.flush_buffer()
+    }
+
+    /// Sends every event received from `events` until all its senders are gone, and a comment
+    /// whenever none came for `keep_alive`. Returns the error of the first failed write, as when
+    /// the client disconnects.
+    pub fn relay(&mut self, events: &Receiver<Event>, keep_alive: Duration) -> io::Result<()> {
+        loop {
+            match events.recv_timeout(keep_alive) {
+                Ok(event) => self.send(&event)?,
+                Err(RecvTimeoutError::Timeout) => self.comment("keep-alive")?,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    fn flush_buffer(&mut self) -> io::Result<()> {
+        self.out.write_all(self.buffer.as_bytes())?;
+        self.out.flush()
+    }
+}
@@ -0,0 +1,430 @@
+use std::{
+    fs,
+    io::{Cursor, Read},
+};
+
+use espresso::{
+    body::Body,
+    error::{EspressoRequestError, HttpError},
+    espresso::Espresso,
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+};
+
+use super::support::{send_raw, serve};
+
+fn echo_app() -> Espresso {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.post(
+        "/bytes",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send(&format!("{:?};", req.body_bytes()));
+        },
+    );
+    app.post(
+        "/text",
+        |req: &EspressoRequest, res: &mut EspressoResponse| -> Result<(), EspressoRequestError> {
+            res.send(req.body_text()?);
+            Ok(())
+        },
+    );
+    app.get("/", |req: &EspressoRequest, res: &mut EspressoResponse| {
+        res.send(&format!("body: {:?};", req.body));
+    });
+    app
+}
+
+#[test]
+pub fn binary_bodies_should_be_read_exactly() {
+    let addr = &serve(echo_app());
+
+    // The body is followed by a second request, which must not be swallowed or corrupted.
+    let mut raw = b"POST /bytes HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4\r\n\r\n".to_vec();
+    raw.extend_from_slice(&[0, 159, 146, 150]);
+    raw.extend_from_slice(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    let response = send_raw(addr, raw);
+    assert!(response.contains("[0, 159, 146, 150];"));
+    assert!(response.contains("body: None;"));
+}
+
+#[test]
+pub fn text_bodies_should_be_checked_for_utf8() {
+    let addr = &serve(echo_app());
+
+    let response = send_raw(
+        addr,
+        "POST /text HTTP/1.1\r\nHost: localhost\r\nContent-Length: 6\r\n\r\nhéllo",
+    );
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("\r\n\r\nhéllo"));
+
+    let mut raw = b"POST /text HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\n\r\n".to_vec();
+    raw.extend_from_slice(&[0xC3, 0x28]);
+    let response = send_raw(addr, raw);
+    assert!(response.starts_with("HTTP/1.1 400"));
+}
+
+#[test]
+pub fn bodies_over_the_limit_should_get_a_413() {
+    let mut app = echo_app();
+    app.config().max_body_size = 8;
+    let addr = &serve(app);
+
+    let response = send_raw(
+        addr,
+        "POST /bytes HTTP/1.1\r\nHost: localhost\r\nContent-Length: 8\r\n\r\n12345678",
+    );
+    assert!(response.starts_with("HTTP/1.1 200"));
+
+    let response = send_raw(
+        addr,
+        "POST /bytes HTTP/1.1\r\nHost: localhost\r\nContent-Length: 9\r\n\r\n123456789",
+    );
+    assert!(response.starts_with("HTTP/1.1 413"));
+    assert!(response.contains("Connection: close\r\n"));
+}
+
+#[test]
+pub fn invalid_content_lengths_should_be_rejected() {
+    for raw in [
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: -1\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: +1\r\n\r\na",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+    ] {
+        assert!(
+            matches!(
+                EspressoRequest::try_from(raw.as_bytes()),
+                Err(EspressoRequestError::MalformedRequest(_))
+            ),
+            "{raw:?} should be rejected"
+        );
+    }
+
+    let request = EspressoRequest::try_from(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 2\r\nContent-Length: 2\r\n\r\nabcd"
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(request.body_bytes(), b"ab");
+
+    assert!(matches!(
+        EspressoRequest::try_from(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nab".as_bytes()
+        ),
+        Err(EspressoRequestError::IncompleteRequest(_))
+    ));
+}
+
+#[test]
+pub fn chunked_bodies_should_be_decoded() {
+    let request = EspressoRequest::try_from(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         5;lang=en\r\nhello\r\n\
+         7 ; sig = \"a;\\\"b\" ; last\r\n, world\r\n\
+         0\r\nChecksum: abc\r\nExpires: never\r\n\r\n\
+         GET / HTTP/1.1\r\n"
+            .as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(request.body_text().unwrap(), "hello, world");
+    assert_eq!(
+        request.chunk_extensions,
+        vec![
+            ("lang".to_string(), "en".to_string()),
+            ("sig".to_string(), "a;\"b".to_string()),
+            ("last".to_string(), String::new()),
+        ]
+    );
+    assert_eq!(request.trailers.get("Checksum"), Some("abc"));
+    assert_eq!(request.trailers.get("Expires"), Some("never"));
+    assert!(!request.headers.contains("Checksum"));
+}
+
+#[test]
+pub fn chunked_bodies_should_keep_pipelined_requests_intact() {
+    let addr = &serve(echo_app());
+
+    let response = send_raw(
+        addr,
+        "POST /text HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         3\r\nabc\r\nA\r\n0123456789\r\n0\r\n\r\n\
+         GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.contains("\r\n\r\nabc0123456789HTTP/1.1 200"));
+    assert!(response.ends_with("body: None;"));
+}
+
+#[test]
+pub fn invalid_chunked_bodies_should_be_rejected() {
+    for raw in [
+        // Both framings at once could be read differently by a proxy.
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nx\r\nabc\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nabc\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3;\r\nabc\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3;a=\"b\r\nabc\r\n0\r\n\r\n",
+        "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\nfffffffffffffffffffff\r\n",
+    ] {
+        assert!(
+            matches!(
+                EspressoRequest::try_from(raw.as_bytes()),
+                Err(EspressoRequestError::MalformedRequest(_))
+            ),
+            "{raw:?} should be rejected"
+        );
+    }
+
+    assert!(matches!(
+        EspressoRequest::try_from(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"
+                .as_bytes()
+        ),
+        Err(EspressoRequestError::UnsupportedTransferEncoding(_))
+    ));
+    assert!(matches!(
+        EspressoRequest::try_from(
+            "POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nab"
+                .as_bytes()
+        ),
+        Err(EspressoRequestError::IncompleteRequest(_))
+    ));
+}
+
+#[test]
+pub fn chunked_bodies_over_the_limit_should_get_a_413() {
+    let mut app = echo_app();
+    app.config().max_body_size = 8;
+    let addr = &serve(app);
+
+    let response = send_raw(
+        addr,
+        "POST /text HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         5\r\n12345\r\n5\r\n67890\r\n0\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 413"));
+}
+
+#[test]
+pub fn chunk_extensions_over_the_limit_should_get_a_413() {
+    let mut app = echo_app();
+    app.config().max_chunk_extensions = 256;
+    let addr = &serve(app);
+
+    // Every chunk stays well under the body limit, its extensions are what add up.
+    let chunks = |count: usize| {
+        format!(
+            "POST /bytes HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+             {}0\r\n\r\n",
+            format!("1;ext={}\r\na\r\n", "x".repeat(100)).repeat(count)
+        )
+    };
+    assert!(send_raw(addr, chunks(2)).starts_with("HTTP/1.1 200"));
+    let response = send_raw(addr, chunks(10));
+    assert!(response.starts_with("HTTP/1.1 413"), "{response}");
+}
+
+#[test]
+pub fn chunk_headers_and_trailers_should_keep_to_the_configured_limits() {
+    let mut app = echo_app();
+    app.config().max_request_line = 64;
+    app.config().max_header_size = 256;
+    let addr = &serve(app);
+
+    let body = |chunk_header: &str, trailers: &str| {
+        format!(
+            "POST /bytes HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+             {chunk_header}\r\na\r\n0\r\n{trailers}\r\n"
+        )
+    };
+    let trailer = |len: usize| format!("X-Trailer: {}\r\n", "x".repeat(len));
+    assert!(send_raw(addr, body("1;a=b", &trailer(100))).starts_with("HTTP/1.1 200"));
+    let long_header = format!("1;ext={}", "x".repeat(100));
+    assert!(send_raw(addr, body(&long_header, "")).starts_with("HTTP/1.1 400"));
+    let response = send_raw(addr, body("1", &trailer(300)));
+    assert!(response.starts_with("HTTP/1.1 431"), "{response}");
+}
+
+fn streaming_app() -> Espresso {
+    let mut app = echo_app();
+    app.config().max_body_size = 16;
+    app.route_streaming(
+        RequestMethod::POST,
+        "/upload",
+        |req: &EspressoRequest, res: &mut EspressoResponse| -> Result<(), HttpError> {
+            let mut total = 0;
+            let mut buf = [0; 7];
+            loop {
+                let read = req.body_reader().read(&mut buf)?;
+                if read == 0 {
+                    break;
+                }
+                total += read;
+            }
+            res.send(&format!("read {total};"));
+            Ok(())
+        },
+    );
+    app.route_streaming(
+        RequestMethod::POST,
+        "/peek",
+        |req: &EspressoRequest, res: &mut EspressoResponse| -> Result<(), HttpError> {
+            let mut buf = [0; 4];
+            req.body_reader().read_exact(&mut buf)?;
+            res.send(&format!("peeked {};", String::from_utf8_lossy(&buf)));
+            Ok(())
+        },
+    );
+    app
+}
+
+#[test]
+pub fn streaming_routes_should_read_bodies_over_the_limit() {
+    let addr = &serve(streaming_app());
+
+    let body = "x".repeat(100_000);
+    let response = send_raw(
+        addr,
+        format!(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}\
+             GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            body.len()
+        ),
+    );
+    assert!(response.contains("read 100000;"));
+    assert!(response.ends_with("body: None;"));
+
+    let response = send_raw(
+        addr,
+        format!(
+            "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+             {:x}\r\n{body}\r\n{:x}\r\n{body}\r\n0\r\n\r\n\
+             GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            body.len(),
+            body.len()
+        ),
+    );
+    assert!(response.contains("read 200000;"));
+    assert!(response.ends_with("body: None;"));
+}
+
+#[test]
+pub fn unread_streamed_bodies_should_be_drained_or_the_connection_closed() {
+    let addr = &serve(streaming_app());
+
+    // What is left is within the body size limit, so it is skipped and the connection kept.
+    let response = send_raw(
+        addr,
+        "POST /peek HTTP/1.1\r\nHost: localhost\r\nContent-Length: 12\r\n\r\nabcdefghijkl\
+         GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.contains("peeked abcd;"));
+    assert!(response.ends_with("body: None;"));
+
+    let response = send_raw(
+        addr,
+        "POST /peek HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         2\r\nab\r\n6\r\ncdefgh\r\n0\r\n\r\n\
+         GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.contains("peeked abcd;"));
+    assert!(response.ends_with("body: None;"));
+
+    // Too much is left, so the connection is closed after the response.
+    let body = "x".repeat(1000);
+    let response = send_raw(
+        addr,
+        format!(
+            "POST /peek HTTP/1.1\r\nHost: localhost\r\nContent-Length: 1000\r\n\r\n{body}\
+             GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+        ),
+    );
+    assert!(response.ends_with("peeked xxxx;"));
+}
+
+#[test]
+pub fn malformed_streamed_bodies_should_fail_the_read() {
+    let addr = &serve(streaming_app());
+
+    let response = send_raw(
+        addr,
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         3\r\nabcdef\r\n0\r\n\r\n\
+         GET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 500"));
+    assert!(!response.contains("body: None;"));
+}
+
+#[test]
+pub fn body_reader_should_read_buffered_bodies() {
+    let request = EspressoRequest::try_from(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello".as_bytes(),
+    )
+    .unwrap();
+    let mut body = String::new();
+    request.body_reader().read_to_string(&mut body).unwrap();
+    assert_eq!(body, "hello");
+}
+
+#[test]
+pub fn file_and_reader_bodies_should_be_served_over_get_and_head() {
+    let path = std::env::temp_dir().join(format!("espresso-body-{}.txt", std::process::id()));
+    fs::write(&path, "file contents").unwrap();
+
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    let file = path.clone();
+    app.get(
+        "/file",
+        move |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.set_header("Content-Type", "text/plain");
+            res.set_body(Body::open(&file)?);
+            Ok::<(), HttpError>(())
+        },
+    );
+    app.get(
+        "/stream",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.set_body(Body::reader(Cursor::new("streamed")));
+        },
+    );
+    app.get(
+        "/declared",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.set_header("Content-Length", "8");
+            res.set_body(Body::reader(Cursor::new("streamed")));
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /file HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.contains("Content-Length: 13\r\n"));
+    assert!(response.ends_with("\r\n\r\nfile contents"));
+
+    let response = send_raw(addr, "HEAD /file HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.contains("Content-Length: 13\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let response = send_raw(
+        addr,
+        "GET /stream HTTP/1.1\r\nHost: localhost\r\n\r\nHEAD /stream HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    let (get, head) = response.split_at(response.rfind("HTTP/1.1 200").unwrap());
+    assert!(get.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(get.ends_with("\r\n\r\n8\r\nstreamed\r\n0\r\n\r\n"));
+    assert!(head.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(head.ends_with("\r\n\r\n"));
+
+    // The length a handler declares for a reader stands, and is what HEAD answers with.
+    let response = send_raw(
+        addr,
+        "GET /declared HTTP/1.1\r\nHost: localhost\r\n\r\nHEAD /declared HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    let (get, head) = response.split_at(response.rfind("HTTP/1.1 200").unwrap());
+    assert!(get.contains("Content-Length: 8\r\n"));
+    assert!(get.ends_with("\r\n\r\nstreamed"));
+    assert!(head.contains("Content-Length: 8\r\n"));
+    assert!(head.ends_with("\r\n\r\n"));
+
+    fs::remove_file(&path).unwrap();
+}
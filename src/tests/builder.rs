@@ -0,0 +1,100 @@
+use std::{
+    io::ErrorKind,
+    thread,
+    time::{Duration, Instant},
+};
+
+use espresso::{
+    builder::EspressoBuilder, espresso::Espresso, request::EspressoRequest,
+    response::EspressoResponse, threads::pigeonhole_threads,
+};
+
+use super::support::{send_raw, serve};
+
+#[test]
+pub fn bind_should_pick_a_port_and_report_failures() {
+    let app = Espresso::bind("127.0.0.1:0").unwrap();
+    let local_addr = app.local_addr().unwrap();
+    assert_ne!(local_addr.port(), 0);
+
+    let taken = Espresso::bind(local_addr).err().unwrap();
+    assert_eq!(taken.kind(), ErrorKind::AddrInUse);
+
+    let no_workers = EspressoBuilder::new()
+        .workers(0)
+        .bind("127.0.0.1:0")
+        .err()
+        .unwrap();
+    assert_eq!(no_workers.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+pub fn builder_should_apply_its_limits() {
+    let mut app = EspressoBuilder::new()
+        .max_request_line(32)
+        .max_header_size(64)
+        .max_body_size(4)
+        .backlog(16)
+        .bind("127.0.0.1:0")
+        .unwrap();
+    app.all(
+        "/*",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("ok");
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+
+    let long_target = format!(
+        "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "a".repeat(32)
+    );
+    assert!(send_raw(addr, long_target).starts_with("HTTP/1.1 414 "));
+
+    let large_headers = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Large: {}\r\n\r\n",
+        "a".repeat(64)
+    );
+    assert!(send_raw(addr, large_headers).starts_with("HTTP/1.1 431 "));
+
+    let large_body = "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello";
+    assert!(send_raw(addr, large_body).starts_with("HTTP/1.1 413 "));
+}
+
+#[test]
+pub fn builder_should_serve_on_the_pool_it_was_given() {
+    let mut app = EspressoBuilder::new()
+        .workers(2)
+        .pool::<pigeonhole_threads::ThreadPool>()
+        .bind("127.0.0.1:0")
+        .unwrap();
+    app.get(
+        "/slow",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            thread::sleep(Duration::from_millis(300));
+            res.send("done");
+        },
+    );
+    let addr = &serve(app);
+
+    // Both workers take a request at once.
+    let start = Instant::now();
+    let requests: Vec<_> = (0..2)
+        .map(|_| {
+            let addr = addr.clone();
+            thread::spawn(move || {
+                send_raw(
+                    &addr,
+                    "GET /slow HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+                )
+            })
+        })
+        .collect();
+    for request in requests {
+        assert!(request.join().unwrap().ends_with("\r\n\r\ndone"));
+    }
+    assert!(start.elapsed() < Duration::from_millis(550));
+}
@@ -0,0 +1,161 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::{Duration, Instant},
+};
+
+use espresso::{espresso::Espresso, request::EspressoRequest, response::EspressoResponse};
+
+use super::support::serve;
+
+/// Serves "hello" on `/` with the settings `configure` makes, and returns the address of the
+/// server.
+fn hello_server(configure: impl FnOnce(&mut Espresso)) -> String {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    configure(&mut app);
+    app.get("/", |_req: &EspressoRequest, res: &mut EspressoResponse| {
+        res.send("hello");
+    });
+    serve(app)
+}
+
+fn connect(addr: &str) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+/// Reads one response to a request for `/`, whose body is "hello".
+fn read_response(stream: &mut TcpStream) -> String {
+    let mut received = Vec::new();
+    let mut buf = [0; 256];
+    while !received.ends_with(b"\r\n\r\nhello") {
+        let read = stream.read(&mut buf).unwrap();
+        assert!(read > 0, "connection closed after {received:?}");
+        received.extend_from_slice(&buf[..read]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+/// Asserts the server closes the connection without sending anything more.
+fn assert_closed(stream: &mut TcpStream) {
+    let mut rest = String::new();
+    stream.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "");
+}
+
+#[test]
+pub fn connections_should_stay_open_until_the_client_closes_them() {
+    let addr = &hello_server(|_| {});
+
+    let mut stream = connect(addr);
+    for _ in 0..3 {
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        assert!(!read_response(&mut stream).contains("Connection:"));
+    }
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    assert!(read_response(&mut stream).contains("Connection: close\r\n"));
+    assert_closed(&mut stream);
+}
+
+#[test]
+pub fn http_1_0_connections_should_only_stay_open_when_asked_to() {
+    let addr = &hello_server(|_| {});
+
+    let mut stream = connect(addr);
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).contains("Connection: close\r\n"));
+    assert_closed(&mut stream);
+
+    let mut stream = connect(addr);
+    for _ in 0..2 {
+        stream
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).contains("Connection: keep-alive\r\n"));
+    }
+    stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    assert!(read_response(&mut stream).contains("Connection: close\r\n"));
+    assert_closed(&mut stream);
+}
+
+#[test]
+pub fn idle_connections_should_be_closed_after_the_idle_timeout() {
+    let addr = &hello_server(|app| {
+        app.config().idle_timeout = Some(Duration::from_millis(200));
+    });
+
+    // Before the first request.
+    let mut stream = connect(addr);
+    let start = Instant::now();
+    assert_closed(&mut stream);
+    assert!(start.elapsed() >= Duration::from_millis(200));
+
+    // Between requests.
+    let mut stream = connect(addr);
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    read_response(&mut stream);
+    assert_closed(&mut stream);
+}
+
+#[test]
+pub fn slow_request_heads_should_time_out() {
+    let addr = &hello_server(|app| {
+        app.config().header_timeout = Some(Duration::from_millis(300));
+    });
+
+    // Every line arrives well within the idle timeout, but the head as a whole takes too long.
+    let mut stream = connect(addr);
+    stream.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_millis(100)))
+        .unwrap();
+    // Send a line every 100 ms, until the answer shows up.
+    for ind in 0.. {
+        assert!(ind < 20, "the request never timed out");
+        if stream.peek(&mut [0]).is_ok() {
+            break;
+        }
+        stream
+            .write_all(format!("X-Slow-{ind}: 1\r\n").as_bytes())
+            .unwrap();
+    }
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(
+        response.starts_with("HTTP/1.1 408 Request Timeout\r\n"),
+        "{response}"
+    );
+    assert!(response.contains("Connection: close\r\n"));
+}
+
+#[test]
+pub fn connections_should_close_after_the_request_limit() {
+    let addr = &hello_server(|app| {
+        app.config().max_requests_per_connection = Some(2);
+    });
+
+    // Pipelined requests past the limit go unanswered.
+    let mut stream = connect(addr);
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n"
+                .repeat(3)
+                .as_slice(),
+        )
+        .unwrap();
+    let mut received = String::new();
+    stream.read_to_string(&mut received).unwrap();
+    let responses: Vec<&str> = received.split_inclusive("hello").collect();
+    assert_eq!(responses.len(), 2, "{received}");
+    assert!(!responses[0].contains("Connection:"));
+    assert!(responses[1].contains("Connection: close\r\n"));
+}
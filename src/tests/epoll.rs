@@ -0,0 +1,298 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use espresso::{
+    builder::{Engine, EspressoBuilder},
+    error::HttpError,
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+};
+
+use super::support::{send_raw, serve};
+
+/// A server on the epoll engine with a single event loop and two workers, with the settings
+/// `configure` makes.
+fn epoll_server(configure: impl FnOnce(EspressoBuilder) -> EspressoBuilder) -> EspressoBuilder {
+    configure(
+        EspressoBuilder::new()
+            .engine(Engine::Epoll)
+            .event_loops(1)
+            .workers(2),
+    )
+}
+
+fn echo_server(configure: impl FnOnce(EspressoBuilder) -> EspressoBuilder) -> String {
+    let mut app = epoll_server(configure).bind("127.0.0.1:0").unwrap();
+    app.get("/", |req: &EspressoRequest, res: &mut EspressoResponse| {
+        let n = req.query().get("n").unwrap_or("none");
+        res.send(&format!("hello {n};"));
+    });
+    app.post(
+        "/echo",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send(&format!(
+                "echo {};",
+                String::from_utf8_lossy(req.body.as_deref().unwrap_or_default())
+            ));
+        },
+    );
+    app.get(
+        "/stream",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.stream(|out| {
+                for part in ["one ", "two ", "three;"] {
+                    out.write_all(part.as_bytes())?;
+                }
+                Ok(())
+            });
+        },
+    );
+    app.route_streaming(
+        RequestMethod::POST,
+        "/upload",
+        |req: &EspressoRequest, res: &mut EspressoResponse| -> Result<(), HttpError> {
+            let mut body = Vec::new();
+            req.body_reader().read_to_end(&mut body)?;
+            res.send(&format!("read {};", body.len()));
+            Ok(())
+        },
+    );
+    serve(app)
+}
+
+fn connect(addr: &str) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+/// Reads one response, up to and including the end of `body`.
+fn read_until(stream: &mut TcpStream, body: &str) -> String {
+    let mut received = Vec::new();
+    let mut buf = [0; 256];
+    while !received.ends_with(body.as_bytes()) {
+        let read = stream.read(&mut buf).unwrap();
+        assert!(read > 0, "connection closed after {received:?}");
+        received.extend_from_slice(&buf[..read]);
+    }
+    String::from_utf8(received).unwrap()
+}
+
+#[test]
+pub fn epoll_should_serve_many_connections_on_a_few_threads() {
+    let addr = &echo_server(|builder| builder);
+
+    let mut streams: Vec<_> = (0..300).map(|_| connect(addr)).collect();
+    for round in 0..2 {
+        for (n, stream) in streams.iter_mut().enumerate() {
+            write!(stream, "GET /?n={n} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        }
+        for (n, stream) in streams.iter_mut().enumerate() {
+            let response = read_until(stream, &format!("\r\n\r\nhello {n};"));
+            assert!(
+                response.starts_with("HTTP/1.1 200 OK\r\n"),
+                "{round}: {response}"
+            );
+        }
+    }
+}
+
+#[test]
+pub fn epoll_should_read_requests_however_they_arrive() {
+    let addr = &echo_server(|builder| builder);
+
+    // Pipelined, with bodies of both framings.
+    let response = send_raw(
+        addr,
+        "GET /?n=1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         \r\n\
+         POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello\
+         POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n\
+         GET /?n=2 HTTP/1.0\r\n\r\n",
+    );
+    let bodies: Vec<_> = response
+        .split("HTTP/1.1 200 OK\r\n")
+        .skip(1)
+        .map(|response| response.split("\r\n\r\n").nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        bodies,
+        ["hello 1;", "echo hello;", "echo abcde;", "hello 2;"]
+    );
+
+    // A byte at a time.
+    let mut stream = connect(addr);
+    let request = "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 3\r\n\r\nabc";
+    for byte in request.bytes() {
+        stream.write_all(&[byte]).unwrap();
+        stream.flush().unwrap();
+        thread::sleep(Duration::from_millis(2));
+    }
+    assert!(read_until(&mut stream, "\r\n\r\necho abc;").starts_with("HTTP/1.1 200 OK\r\n"));
+
+    let large_headers = format!(
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Large: {}\r\n\r\n",
+        "a".repeat(100_000)
+    );
+    assert!(send_raw(addr, large_headers).starts_with("HTTP/1.1 431 "));
+    let cut_short = "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\nabc";
+    assert!(send_raw(addr, cut_short).starts_with("HTTP/1.1 400 "));
+}
+
+#[test]
+pub fn epoll_should_decode_chunked_bodies_as_they_arrive() {
+    let addr = &echo_server(|builder| builder.max_body_size(300_000));
+
+    // The framing is five times the size of the data, which is as large as allowed.
+    let chunks = "1\r\nx\r\n".repeat(300_000);
+    let response = send_raw(
+        addr,
+        format!(
+            "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+             {chunks}0\r\n\r\n"
+        ),
+    );
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&format!("\r\n\r\necho {};", "x".repeat(300_000))));
+
+    let too_large = format!(
+        "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+         {chunks}1\r\nx\r\n0\r\n\r\n"
+    );
+    assert!(send_raw(addr, too_large).starts_with("HTTP/1.1 413 "));
+
+    // Cut off anywhere: in chunk headers, extensions, line endings and the trailer section.
+    let mut stream = connect(addr);
+    let request = "POST /echo HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+                   3;name=\"value\"\r\nabc\r\n10\r\ndefghijklmnopqrs\r\n0\r\nX-Trailer: yes\r\n\r\n";
+    for byte in request.bytes() {
+        stream.write_all(&[byte]).unwrap();
+        stream.flush().unwrap();
+        thread::sleep(Duration::from_millis(1));
+    }
+    let response = read_until(&mut stream, "\r\n\r\necho abcdefghijklmnopqrs;");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+}
+
+#[test]
+pub fn epoll_should_hand_streamed_bodies_to_the_workers() {
+    let addr = &echo_server(|builder| builder.max_body_size(16));
+
+    let upload = "x".repeat(100_000);
+    let mut stream = connect(addr);
+    write!(
+        stream,
+        "POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{upload}\
+         GET /stream HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        upload.len()
+    )
+    .unwrap();
+    let responses = read_until(&mut stream, "\r\n0\r\n\r\n");
+    assert!(responses.contains("\r\n\r\nread 100000;HTTP/1.1 200 OK\r\n"));
+    assert!(responses.contains("\r\nthree;\r\n"));
+
+    // The connection is back on the event loop, which applies the limits again.
+    stream
+        .write_all(b"POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello")
+        .unwrap();
+    assert!(read_until(&mut stream, "\r\n\r\necho hello;").starts_with("HTTP/1.1 200 OK\r\n"));
+    let too_large = "POST /echo HTTP/1.1\r\nHost: localhost\r\nContent-Length: 17\r\n\r\n";
+    stream.write_all(too_large.as_bytes()).unwrap();
+    let mut rest = String::new();
+    stream.read_to_string(&mut rest).unwrap();
+    assert!(rest.starts_with("HTTP/1.1 413 "), "{rest}");
+}
+
+#[test]
+pub fn epoll_should_time_connections_out() {
+    let addr = &echo_server(|builder| {
+        builder
+            .idle_timeout(Some(Duration::from_millis(300)))
+            .header_timeout(Some(Duration::from_millis(500)))
+            .max_requests_per_connection(Some(2))
+    });
+
+    let mut stream = connect(addr);
+    let start = Instant::now();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    assert!(start.elapsed() >= Duration::from_millis(300));
+
+    // Sending a byte every so often keeps the connection from going idle, not the head from
+    // being late.
+    let mut stream = connect(addr);
+    let start = Instant::now();
+    for byte in b"GET / HTTP/1.1\r\n" {
+        if stream.write_all(&[*byte]).is_err() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 408 "), "{response}");
+    assert!(start.elapsed() < Duration::from_secs(1));
+
+    let response = send_raw(
+        addr,
+        "GET /?n=1 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /?n=2 HTTP/1.1\r\nHost: localhost\r\n\r\n\
+         GET /?n=3 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with("hello 2;"), "{response}");
+}
+
+#[test]
+pub fn epoll_should_shut_down_gracefully() {
+    let mut app = epoll_server(|builder| builder.idle_timeout(Some(Duration::from_secs(60))))
+        .bind("127.0.0.1:0")
+        .unwrap();
+    app.get(
+        "/slow",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            thread::sleep(Duration::from_millis(300));
+            res.send("finally");
+        },
+    );
+    let addr = &app.local_addr().unwrap().to_string();
+    let handle = app.spawn();
+
+    let mut idle = connect(addr);
+    idle.write_all(b"GET /none HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    assert!(read_until(&mut idle, "\r\n\r\n").starts_with("HTTP/1.1 404 "));
+    let mut busy = connect(addr);
+    busy.write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut fresh = connect(addr);
+    thread::sleep(Duration::from_millis(100));
+
+    let start = Instant::now();
+    handle.shutdown();
+    // A connection accepted before the shutdown still gets its first request answered.
+    fresh
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut rest = Vec::new();
+    idle.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    let mut response = String::new();
+    busy.read_to_string(&mut response).unwrap();
+    assert!(response.contains("Connection: close\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nfinally"));
+    let mut response = String::new();
+    fresh.read_to_string(&mut response).unwrap();
+    assert!(response.contains("Connection: close\r\n"), "{response}");
+    assert!(response.ends_with("\r\n\r\nfinally"));
+    handle.wait();
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
@@ -0,0 +1,100 @@
+use espresso::{
+    error::{EspressoRequestError, HttpError},
+    espresso::Espresso,
+    request::EspressoRequest,
+    response::EspressoResponse,
+    router::Router,
+};
+
+use super::support::{send_raw, serve};
+
+#[test]
+pub fn unhandled_errors_should_answer_with_their_status() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/fails",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| -> Result<(), HttpError> {
+            res.send("partial output");
+            Err(HttpError::internal("database is down"))
+        },
+    );
+    app.get(
+        "/bad",
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| -> Result<(), EspressoRequestError> {
+            Err(EspressoRequestError::MalformedRequest(
+                "missing field".to_string(),
+            ))
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /fails HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 500"));
+    assert!(!response.contains("partial output"));
+    assert!(!response.contains("database is down"));
+
+    let response = send_raw(addr, "GET /bad HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400"));
+    assert!(response.ends_with("missing field"));
+}
+
+#[test]
+pub fn error_middleware_should_run_innermost_first() {
+    let mut api = Router::new();
+    api.get(
+        "/missing",
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| -> Result<(), HttpError> {
+            Err(HttpError::not_found("no such thing"))
+        },
+    );
+    api.error_middleware(
+        |err: HttpError,
+         _req: &EspressoRequest,
+         _res: &mut EspressoResponse|
+         -> Result<(), HttpError> {
+            Err(HttpError::new(err.status, format!("api: {}", err.message)))
+        },
+    );
+
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.error_middleware(
+        |err: HttpError, _req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.set_status(err.status);
+            res.send(&format!("handled {}", err.message));
+        },
+    );
+    app.mount("/api", api);
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /api/missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404"));
+    assert!(response.ends_with("handled api: no such thing"));
+}
+
+#[test]
+pub fn panicking_handlers_should_answer_500_and_keep_serving() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/panic/:id",
+        |req: &EspressoRequest, _res: &mut EspressoResponse| -> () {
+            panic!("handler for {} blew up", req.params().get("id").unwrap());
+        },
+    );
+    app.get(
+        "/ok",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("still alive");
+        },
+    );
+    let addr = &serve(app);
+
+    for id in 0..3 {
+        let response = send_raw(
+            addr,
+            format!("GET /panic/{id} HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+        );
+        assert!(response.starts_with("HTTP/1.1 500"));
+    }
+    let response = send_raw(addr, "GET /ok HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.ends_with("still alive"));
+}
@@ -0,0 +1,73 @@
+use espresso::{
+    espresso::Espresso, headers::HeaderMap, request::EspressoRequest, response::EspressoResponse,
+};
+
+use super::support::{send_raw, serve};
+
+#[test]
+pub fn header_map_should_compare_names_case_insensitively() {
+    let mut headers = HeaderMap::new();
+    headers.append("Content-Type", " text/html\t");
+    headers.append("set-cookie", "a=1");
+    headers.append("Set-Cookie", "b=2");
+
+    assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+    assert_eq!(headers.content_type(), Some("text/html"));
+    assert_eq!(
+        headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+        vec!["a=1", "b=2"]
+    );
+    assert_eq!(headers.len(), 3);
+
+    headers.insert("SET-COOKIE", "c=3");
+    assert_eq!(
+        headers.get_all("set-cookie").collect::<Vec<_>>(),
+        vec!["c=3"]
+    );
+    assert!(headers.remove("content-type"));
+    assert!(!headers.remove("content-type"));
+    assert_eq!(
+        headers.iter().collect::<Vec<_>>(),
+        vec![("SET-COOKIE", "c=3")]
+    );
+}
+
+#[test]
+pub fn header_map_should_offer_typed_accessors() {
+    let headers: HeaderMap = [
+        ("host", "example.com"),
+        ("Content-Length", "42"),
+        ("Accept", "text/html, application/json;q=0.9"),
+        ("accept", "*/*;q=0.1"),
+        ("Authorization", "Bearer token"),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(headers.host(), Some("example.com"));
+    assert_eq!(headers.content_length(), Some(42));
+    assert_eq!(
+        headers.accept(),
+        vec!["text/html", "application/json;q=0.9", "*/*;q=0.1"]
+    );
+    assert_eq!(headers.authorization(), Some("Bearer token"));
+    assert_eq!(headers.content_type(), None);
+
+    let headers: HeaderMap = [("Content-Length", "-1")].into_iter().collect();
+    assert_eq!(headers.content_length(), None);
+}
+
+#[test]
+pub fn responses_should_keep_header_casing_and_repeated_values() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get("/", |req: &EspressoRequest, res: &mut EspressoResponse| {
+        res.append_header("Set-Cookie", "a=1");
+        res.append_header("Set-Cookie", "b=2");
+        res.set_header("x-request-host", req.get_header("HOST").unwrap_or(""));
+        res.send("ok");
+    });
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET / HTTP/1.1\r\nhost: localhost\r\n\r\n");
+    assert!(response.contains("\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n"));
+    assert!(response.contains("\r\nx-request-host: localhost\r\n"));
+}
@@ -0,0 +1,77 @@
+use espresso::{
+    espresso::Espresso,
+    middleware::{from_fn, EspressoMiddleware, Next},
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+    router::Router,
+};
+
+use super::support::{send_raw, serve};
+
+fn tag(name: &'static str) -> EspressoMiddleware {
+    from_fn(
+        move |req: &mut EspressoRequest, res: &mut EspressoResponse, next: Next| {
+            res.send(&format!("<{name}"));
+            let result = next.run(req, res);
+            res.send(&format!("{name}>"));
+            result
+        },
+    )
+}
+
+#[test]
+pub fn middleware_should_wrap_handlers_in_order() {
+    let mut admin = Router::new();
+    admin.middleware(tag("admin"));
+    admin.route_with(
+        RequestMethod::GET,
+        "/stats",
+        vec![tag("route")],
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("stats");
+        },
+    );
+
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.middleware(tag("global"));
+    app.middleware_at("/admin", tag("prefix"));
+    app.middleware_at("/other", tag("other"));
+    app.mount("/admin", admin);
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /admin/stats HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.ends_with("<global<prefix<admin<routestatsroute>admin>prefix>global>"));
+
+    let response = send_raw(addr, "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404"));
+    assert!(response.ends_with("<globalglobal>"));
+}
+
+#[test]
+pub fn middleware_should_be_able_to_short_circuit() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.middleware(
+        |req: &mut EspressoRequest, res: &mut EspressoResponse, next: Next| {
+            if req.params().get("id") == Some("0") {
+                res.status(400);
+                res.send("denied");
+                return Ok(());
+            }
+            next.run(req, res)
+        },
+    );
+    app.get(
+        "/items/:id",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("item");
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /items/0 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400"));
+    assert!(response.ends_with("denied"));
+
+    let response = send_raw(addr, "GET /items/1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.ends_with("item"));
+}
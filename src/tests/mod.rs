@@ -5,6 +5,25 @@ use std::{
 };
 
 use espresso::threads::{pigeonhole_threads, stream_threads, TPool};
+
+mod bodies;
+mod builder;
+mod connections;
+#[cfg(target_os = "linux")]
+mod epoll;
+mod errors;
+mod headers;
+mod middleware;
+mod parser;
+mod routing;
+mod shutdown;
+mod status;
+mod streaming;
+mod support;
+mod url;
+mod websocket;
+mod writer;
+
 #[test]
 pub fn thread_pool_should_process_asynchronously() {
     let pool = pigeonhole_threads::ThreadPool::new(2);
@@ -33,6 +52,8 @@ pub fn thread_pool_should_be_decently_performant() {
             *t.lock().unwrap() += 1;
         });
     }
+    // Dropping the pool waits for the queued jobs.
+    drop(pool);
     assert!(*result.lock().unwrap() == 1000000);
 }
 
@@ -53,3 +74,43 @@ pub fn thread_pool_should_process_asynchronously_ws() {
     thread::sleep(Duration::from_millis(110));
     assert!(*result.lock().unwrap() == 2);
 }
+
+#[test]
+pub fn thread_pool_should_survive_panicking_jobs() {
+    let pool = stream_threads::ThreadPool::new(1);
+    let result = Arc::new(Mutex::new(0));
+    pool.exec(|| panic!("job failed"));
+    let t = Arc::clone(&result);
+    pool.exec(move || {
+        *t.lock().unwrap() += 1;
+    });
+    drop(pool);
+    assert!(*result.lock().unwrap() == 1);
+}
+
+/// A panic payload that panics again when dropped, which takes down the worker that caught it.
+struct PanicOnDrop;
+
+impl Drop for PanicOnDrop {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            panic!("payload dropped");
+        }
+    }
+}
+
+#[test]
+pub fn thread_pool_should_replace_dead_workers_and_stop_cleanly() {
+    let pool = stream_threads::ThreadPool::new(1);
+    let result = Arc::new(Mutex::new(0));
+    pool.exec(|| std::panic::panic_any(PanicOnDrop));
+    let t = Arc::clone(&result);
+    pool.exec(move || {
+        *t.lock().unwrap() += 1;
+    });
+    thread::sleep(Duration::from_millis(50));
+    // A worker dying as the pool is dropped neither makes the drop panic nor outlives the pool.
+    pool.exec(|| std::panic::panic_any(PanicOnDrop));
+    drop(pool);
+    assert!(*result.lock().unwrap() == 1);
+}
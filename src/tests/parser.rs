@@ -0,0 +1,143 @@
+use espresso::{
+    error::EspressoRequestError,
+    espresso::Espresso,
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+};
+
+use super::support::{send_raw, serve};
+
+fn parse(raw: &str) -> Result<EspressoRequest, EspressoRequestError> {
+    EspressoRequest::try_from(raw.as_bytes())
+}
+
+#[test]
+pub fn parser_should_read_the_request_line_and_headers() {
+    let request = parse(
+        "POST /items?page=2 HTTP/1.1\r\nHost: localhost\r\nContent-Type:  text/plain \t\r\nX-Empty:\r\n\r\nhello",
+    )
+    .unwrap();
+    assert_eq!(request.method, RequestMethod::POST);
+    assert_eq!(request.resource, "/items?page=2");
+    assert_eq!(request.protocol_ver, "HTTP/1.1");
+    assert_eq!(request.headers.get("Host"), Some("localhost"));
+    assert_eq!(request.headers.get("Content-Type"), Some("text/plain"));
+    assert_eq!(request.headers.get("X-Empty"), Some(""));
+    assert_eq!(request.body_text().unwrap(), "hello");
+}
+
+#[test]
+pub fn parser_should_accept_bare_lf_and_leading_empty_lines() {
+    let request = parse("\r\n\nGET / HTTP/1.1\nHost: localhost\n\n").unwrap();
+    assert_eq!(request.resource, "/");
+    assert_eq!(request.headers.get("Host"), Some("localhost"));
+}
+
+#[test]
+pub fn parser_should_keep_every_value_of_repeated_headers() {
+    let request =
+        parse("GET / HTTP/1.1\r\nHost: localhost\r\nAccept: a\r\naccept: b\r\n\r\n").unwrap();
+    assert_eq!(
+        request.headers.get_all("accept").collect::<Vec<_>>(),
+        vec!["a", "b"]
+    );
+    assert_eq!(request.headers.get_joined("ACCEPT").unwrap(), "a, b");
+    assert_eq!(request.headers.accept(), vec!["a", "b"]);
+}
+
+#[test]
+pub fn parser_should_reject_malformed_request_lines() {
+    for raw in [
+        "GET /\r\nHost: localhost\r\n\r\n",
+        "GET  / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "GET / HTTP/1.1 extra\r\nHost: localhost\r\n\r\n",
+        "GET / HTTPS/1.1\r\nHost: localhost\r\n\r\n",
+        "GET / HTTP/1\r\nHost: localhost\r\n\r\n",
+        "G(T / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "GET /a\rb HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    ] {
+        assert!(
+            matches!(parse(raw), Err(EspressoRequestError::MalformedRequest(_))),
+            "{raw:?} should be rejected"
+        );
+    }
+    assert!(matches!(
+        parse("GET / HTTP/2.0\r\n\r\n"),
+        Err(EspressoRequestError::UnsupportedVersion(_))
+    ));
+}
+
+#[test]
+pub fn parser_should_reject_malformed_header_fields() {
+    for raw in [
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Folded: a\r\n b\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Space : a\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost: localhost\r\nNo colon\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Nul: a\0b\r\n\r\n",
+        "GET / HTTP/1.1\r\n\r\n",
+        "GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n",
+    ] {
+        assert!(
+            matches!(parse(raw), Err(EspressoRequestError::MalformedRequest(_))),
+            "{raw:?} should be rejected"
+        );
+    }
+    // HTTP/1.0 requests don't need a Host header.
+    assert!(parse("GET / HTTP/1.0\r\n\r\n").is_ok());
+}
+
+#[test]
+pub fn parser_should_reject_incomplete_and_oversized_requests() {
+    assert!(matches!(
+        parse(""),
+        Err(EspressoRequestError::IncompleteRequest(_))
+    ));
+    assert!(matches!(
+        parse("GET / HTTP/1.1\r\nHost: localhost\r\n"),
+        Err(EspressoRequestError::IncompleteRequest(_))
+    ));
+
+    let long_target = format!(
+        "GET /{} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        "a".repeat(9000)
+    );
+    assert!(matches!(
+        parse(&long_target),
+        Err(EspressoRequestError::UriTooLong)
+    ));
+
+    let mut many_headers = "GET / HTTP/1.1\r\nHost: localhost\r\n".to_string();
+    for ind in 0..2000 {
+        many_headers.push_str(&format!("X-Header-{ind}: {}\r\n", "v".repeat(30)));
+    }
+    many_headers.push_str("\r\n");
+    assert!(matches!(
+        parse(&many_headers),
+        Err(EspressoRequestError::HeaderTooLarge)
+    ));
+}
+
+#[test]
+pub fn malformed_requests_should_get_a_400_and_a_closed_connection() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get("/", |_req: &EspressoRequest, res: &mut EspressoResponse| {
+        res.send("root");
+    });
+    let addr = &serve(app);
+
+    // The second request is never answered because the connection is closed after the first.
+    let response = send_raw(
+        addr,
+        "GET / HTTP/1.1\r\nHost: localhost\r\nX-Folded: a\r\n b\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 400"));
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(!response.contains("root"));
+
+    let response = send_raw(addr, "GET / HTTP/3.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 505"));
+
+    let response = send_raw(addr, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("root"));
+}
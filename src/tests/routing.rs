@@ -0,0 +1,523 @@
+use espresso::{
+    error::EspressoRouteError,
+    espresso::Espresso,
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+    route::{Params, RoutePattern},
+    router::{RouteMatch, Router},
+};
+
+use super::support::{send_raw, serve};
+
+#[test]
+pub fn method_routes_should_only_answer_their_method() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/items",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("listing");
+        },
+    );
+    app.post(
+        "/items",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("created");
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /items HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("listing"));
+
+    let response = send_raw(addr, "POST /items HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("created"));
+}
+
+#[test]
+pub fn unregistered_method_should_get_405_with_allow() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/items",
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+    );
+    app.put(
+        "/items",
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "DELETE /items HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 405"));
+    assert!(response.contains("Allow: GET, HEAD, OPTIONS, PUT\r\n"));
+
+    let response = send_raw(addr, "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404"));
+}
+
+/// The params a router with a single route for `pattern` captures from `path`, `None` if the
+/// route doesn't match.
+fn params_of(pattern: &str, path: &str) -> Option<Params> {
+    let mut router = Router::new();
+    router.get(
+        pattern,
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+    );
+    match router.find(&RequestMethod::GET, path) {
+        RouteMatch::Found { params, .. } => Some(params),
+        _ => None,
+    }
+}
+
+#[test]
+pub fn patterns_should_capture_params_and_wildcards() {
+    let params = params_of("/users/:id", "/users/42").unwrap();
+    assert_eq!(params.get("id"), Some("42"));
+    assert!(params_of("/users/:id", "/users").is_none());
+    assert!(params_of("/users/:id", "/users/42/posts").is_none());
+
+    let params = params_of("/posts/:slug?/comments", "/posts/hello/comments").unwrap();
+    assert_eq!(params.get("slug"), Some("hello"));
+    let params = params_of("/posts/:slug?/comments", "/posts/comments").unwrap();
+    assert_eq!(params.get("slug"), None);
+
+    let params = params_of("/static/*path", "/static/css/site.css").unwrap();
+    assert_eq!(params.get("path"), Some("css/site.css"));
+    let params = params_of("/static/*path", "/static").unwrap();
+    assert_eq!(params.get("path"), Some(""));
+
+    let params = params_of("/files/*", "/files/a/b").unwrap();
+    assert_eq!(params.get("*"), Some("a/b"));
+}
+
+#[test]
+pub fn invalid_patterns_should_be_rejected() {
+    assert!(RoutePattern::parse("/a/*/b").is_err());
+    assert!(RoutePattern::parse("/a/:/b").is_err());
+    assert!(RoutePattern::parse("/a/:id/:id").is_err());
+}
+
+#[test]
+pub fn handlers_should_see_their_params() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/users/:id/posts/:post",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            let params = req.params();
+            res.send(&format!(
+                "{}:{}",
+                params.get("id").unwrap(),
+                params.get("post").unwrap()
+            ));
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(
+        addr,
+        "GET /users/42/posts/7 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.ends_with("42:7"));
+}
+
+fn matched_body(router: &Router, method: RequestMethod, path: &str) -> Option<String> {
+    match router.find(&method, path) {
+        RouteMatch::Found { handler, .. } => {
+            let mut response = EspressoResponse::new();
+            let request = EspressoRequest::try_from(
+                format!(
+                    "{} {path} HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                    method.as_str()
+                )
+                .as_bytes(),
+            )
+            .ok()?;
+            handler(&request, &mut response).ok()?;
+            String::from_utf8(response.body.as_bytes()?.to_vec()).ok()
+        }
+        _ => None,
+    }
+}
+
+#[test]
+pub fn router_should_prefer_static_over_param_over_wildcard() {
+    let mut router = Router::new();
+    router.get(
+        "/files/*rest",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("wildcard");
+        },
+    );
+    router.get(
+        "/files/:name",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("param");
+        },
+    );
+    router.get(
+        "/files/readme",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("static");
+        },
+    );
+
+    assert_eq!(
+        matched_body(&router, RequestMethod::GET, "/files/readme").as_deref(),
+        Some("static")
+    );
+    assert_eq!(
+        matched_body(&router, RequestMethod::GET, "/files/other").as_deref(),
+        Some("param")
+    );
+    assert_eq!(
+        matched_body(&router, RequestMethod::GET, "/files/a/b").as_deref(),
+        Some("wildcard")
+    );
+
+    match router.find(&RequestMethod::GET, "/files/a/b") {
+        RouteMatch::Found { params, .. } => assert_eq!(params.get("rest"), Some("a/b")),
+        _ => panic!("Expected the wildcard route to match"),
+    }
+}
+
+#[test]
+pub fn router_should_fall_back_to_a_lower_precedence_route_for_the_method() {
+    let mut router = Router::new();
+    router.get(
+        "/users/me",
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+    );
+    router.post(
+        "/users/:id",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("param");
+        },
+    );
+
+    assert_eq!(
+        matched_body(&router, RequestMethod::POST, "/users/me").as_deref(),
+        Some("param")
+    );
+    match router.find(&RequestMethod::DELETE, "/users/me") {
+        RouteMatch::MethodNotAllowed(allowed) => {
+            assert_eq!(allowed, vec!["GET", "HEAD", "OPTIONS", "POST"])
+        }
+        _ => panic!("Expected a method mismatch"),
+    }
+}
+
+#[test]
+pub fn router_should_find_routes_in_a_deep_ambiguous_tree() {
+    // At every depth the path could go on through a static segment or a parameter, and only the
+    // last segment tells the routes apart.
+    const DEPTH: usize = 32;
+    let noop = |_req: &EspressoRequest, _res: &mut EspressoResponse| {};
+    let mut router = Router::new();
+    for statics in 0..=DEPTH {
+        let params: String = (statics..DEPTH).map(|n| format!("/:p{n}")).collect();
+        router.get(&format!("{}{params}/end", "/a".repeat(statics)), noop);
+    }
+    let all_params: String = (0..DEPTH).map(|n| format!("/:p{n}")).collect();
+    router.post(&format!("{all_params}/other"), noop);
+    router.get("/*rest", noop);
+
+    let prefix = "/a".repeat(DEPTH);
+    match router.find(&RequestMethod::GET, &format!("{prefix}/end")) {
+        RouteMatch::Found { pattern, .. } => assert_eq!(pattern, format!("{prefix}/end")),
+        _ => panic!("Expected the static route to match"),
+    }
+    match router.find(&RequestMethod::POST, &format!("{prefix}/other")) {
+        RouteMatch::Found {
+            pattern, params, ..
+        } => {
+            assert_eq!(pattern, format!("{all_params}/other"));
+            assert_eq!(params.get("p31"), Some("a"));
+        }
+        _ => panic!("Expected the parameter route to match"),
+    }
+    match router.find(&RequestMethod::GET, &format!("{prefix}/other")) {
+        RouteMatch::Found { params, .. } => {
+            assert_eq!(params.get("rest"), Some(&format!("{prefix}/other")[1..]))
+        }
+        _ => panic!("Expected the wildcard route to match"),
+    }
+    match router.find(&RequestMethod::DELETE, &format!("{prefix}/other")) {
+        RouteMatch::MethodNotAllowed(allowed) => {
+            assert_eq!(allowed, vec!["GET", "HEAD", "OPTIONS", "POST"])
+        }
+        _ => panic!("Expected a method mismatch"),
+    }
+}
+
+#[test]
+pub fn router_should_reject_ambiguous_routes() {
+    let mut router = Router::new();
+    router
+        .try_route(
+            RequestMethod::GET,
+            "/users/:id",
+            |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+        )
+        .unwrap();
+    assert!(matches!(
+        router.try_route(
+            RequestMethod::GET,
+            "/users/:name",
+            |_req: &EspressoRequest, _res: &mut EspressoResponse| {}
+        ),
+        Err(EspressoRouteError::Conflict(_))
+    ));
+    assert!(matches!(
+        router.try_route(
+            RequestMethod::GET,
+            "/users/:id?",
+            |_req: &EspressoRequest, _res: &mut EspressoResponse| {}
+        ),
+        Err(EspressoRouteError::Conflict(_))
+    ));
+    // The failed registration of the optional shape must not have claimed `/users` either.
+    router
+        .try_route(
+            RequestMethod::GET,
+            "/users",
+            |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+        )
+        .unwrap();
+    router
+        .try_route(
+            RequestMethod::POST,
+            "/users/:name",
+            |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+        )
+        .unwrap();
+}
+
+#[test]
+pub fn mounted_routers_should_see_the_path_without_their_prefix() {
+    let mut admin = Router::new();
+    admin.get(
+        "/users/:id",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send(&format!(
+                "{} {} {}",
+                req.base_url(),
+                req.path(),
+                req.params().get("id").unwrap()
+            ));
+        },
+    );
+    let mut api = Router::new();
+    api.get("/", |_req: &EspressoRequest, res: &mut EspressoResponse| {
+        res.send("api root");
+    });
+    api.mount("/admin", admin);
+
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/*",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("catch-all");
+        },
+    );
+    app.mount("/api", api);
+    let addr = &serve(app);
+
+    let response = send_raw(
+        addr,
+        "GET /api/admin/users/7 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.ends_with("/api/admin /users/7 7"));
+    let response = send_raw(addr, "GET /api HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.ends_with("api root"));
+    let response = send_raw(addr, "GET /api/unknown HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.ends_with("catch-all"));
+}
+
+#[test]
+pub fn mounting_twice_at_the_same_prefix_should_fail() {
+    let mut router = Router::new();
+    router.try_mount("/api", Router::new()).unwrap();
+    assert!(matches!(
+        router.try_mount("/api/", Router::new()),
+        Err(EspressoRouteError::Conflict(_))
+    ));
+    assert!(matches!(
+        router.try_mount("/users/:id", Router::new()),
+        Err(EspressoRouteError::InvalidPattern(_))
+    ));
+}
+
+#[test]
+pub fn every_standard_method_should_be_parsed() {
+    for (name, method) in [
+        ("GET", RequestMethod::GET),
+        ("POST", RequestMethod::POST),
+        ("PUT", RequestMethod::PUT),
+        ("PATCH", RequestMethod::PATCH),
+        ("DELETE", RequestMethod::DELETE),
+        ("OPTIONS", RequestMethod::OPTIONS),
+        ("HEAD", RequestMethod::HEAD),
+        ("TRACE", RequestMethod::TRACE),
+        ("CONNECT", RequestMethod::CONNECT),
+    ] {
+        assert_eq!(name.parse::<RequestMethod>().unwrap(), method);
+        assert_eq!(method.as_str(), name);
+    }
+    assert_eq!(
+        "PROPFIND".parse::<RequestMethod>().unwrap(),
+        RequestMethod::Extension("PROPFIND".to_string())
+    );
+    // Method names are case-sensitive.
+    assert_eq!(
+        "get".parse::<RequestMethod>().unwrap(),
+        RequestMethod::Extension("get".to_string())
+    );
+    assert!("PRO FIND".parse::<RequestMethod>().is_err());
+    assert!("".parse::<RequestMethod>().is_err());
+
+    let request = EspressoRequest::try_from(
+        "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com\r\n\r\n".as_bytes(),
+    )
+    .unwrap();
+    assert_eq!(request.method, RequestMethod::CONNECT);
+    assert_eq!(request.resource, "example.com:443");
+}
+
+#[test]
+pub fn extension_methods_should_be_routable() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.route(
+        "PURGE".parse().unwrap(),
+        "/cache/:key",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send(&format!("purged {}", req.params().get("key").unwrap_or("")));
+        },
+    );
+    app.router().route(
+        RequestMethod::Extension("PROPFIND".to_string()),
+        "/cache/:key",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("properties");
+        },
+    );
+    app.patch(
+        "/cache/:key",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("patched");
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(
+        addr,
+        "PURGE /cache/home HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.ends_with("purged home"));
+    let response = send_raw(
+        addr,
+        "PROPFIND /cache/home HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.ends_with("properties"));
+    let response = send_raw(
+        addr,
+        "PATCH /cache/home HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.ends_with("patched"));
+
+    let response = send_raw(
+        addr,
+        "MKCOL /cache/home HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 405"));
+    assert!(response.contains("Allow: OPTIONS, PATCH, PROPFIND, PURGE\r\n"));
+}
+
+#[test]
+pub fn head_should_run_the_get_handler_without_sending_the_body() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/page",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.set_header("X-Method", req.method.as_str());
+            res.send("0123456789");
+        },
+    );
+    app.get(
+        "/custom",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("from get");
+        },
+    );
+    app.head(
+        "/custom",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.set_header("X-Handler", "head");
+            res.send("ignored");
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "HEAD /page HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("Content-Length: 10\r\n"));
+    assert!(response.contains("X-Method: HEAD\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let response = send_raw(addr, "HEAD /custom HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.contains("X-Handler: head\r\n"));
+    assert!(response.contains("Content-Length: 7\r\n"));
+    assert!(response.ends_with("\r\n\r\n"));
+
+    let response = send_raw(addr, "HEAD /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404"));
+    assert!(response.ends_with("\r\n\r\n"));
+}
+
+#[test]
+pub fn options_should_list_the_methods_of_the_path() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/items",
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+    );
+    app.delete(
+        "/items/:id",
+        |_req: &EspressoRequest, _res: &mut EspressoResponse| {},
+    );
+    app.options(
+        "/custom",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("custom options");
+        },
+    );
+    app.middleware(
+        |req: &mut EspressoRequest,
+         res: &mut EspressoResponse,
+         next: espresso::middleware::Next| {
+            res.set_header("Access-Control-Allow-Origin", "*");
+            next.run(req, res)
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "OPTIONS /items HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(response.contains("Allow: GET, HEAD, OPTIONS\r\n"));
+    // Middleware still runs, so CORS preflights can be answered.
+    assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+
+    let response = send_raw(addr, "OPTIONS /items/7 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.contains("Allow: DELETE, OPTIONS\r\n"));
+
+    let response = send_raw(addr, "OPTIONS /custom HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.ends_with("custom options"));
+
+    let response = send_raw(addr, "OPTIONS /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404"));
+
+    let response = send_raw(addr, "OPTIONS * HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200"));
+    assert!(!response.contains("Allow:"));
+}
@@ -0,0 +1,181 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::{Duration, Instant},
+};
+
+use espresso::{
+    espresso::Espresso,
+    request::{EspressoRequest, RequestMethod},
+    response::EspressoResponse,
+    router::RouteMatch,
+};
+
+fn slow_server() -> Espresso {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get("/", |_req: &EspressoRequest, res: &mut EspressoResponse| {
+        res.send("hello");
+    });
+    app.get(
+        "/slow",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            thread::sleep(Duration::from_millis(300));
+            res.send("finally");
+        },
+    );
+    app.get(
+        "/forever",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.stream(|out| loop {
+                out.write_all(b"tick")?;
+                thread::sleep(Duration::from_millis(20));
+            });
+        },
+    );
+    app
+}
+
+fn connect(addr: &str) -> TcpStream {
+    let stream = TcpStream::connect(addr).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    stream
+}
+
+/// Asserts nothing answers requests on `addr` anymore.
+fn assert_stopped(addr: &str) {
+    if let Ok(mut stream) = TcpStream::connect(addr) {
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let _ = stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(response.is_empty(), "{response:?}");
+    }
+}
+
+#[test]
+pub fn shutdown_should_let_requests_in_flight_finish() {
+    let app = slow_server();
+    let addr = &app.local_addr().unwrap().to_string();
+    let handle = app.spawn();
+
+    let mut stream = connect(addr);
+    stream
+        .write_all(b"GET /slow HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    handle.shutdown();
+    assert!(handle.is_shutting_down());
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nfinally"));
+
+    handle.wait();
+    assert_stopped(addr);
+}
+
+#[test]
+pub fn shutdown_should_answer_the_first_request_of_new_connections() {
+    let app = slow_server();
+    let addr = &app.local_addr().unwrap().to_string();
+    let handle = app.spawn();
+
+    let mut stream = connect(addr);
+    // Accepted, but nothing sent yet.
+    thread::sleep(Duration::from_millis(100));
+    handle.shutdown();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(response.ends_with("\r\n\r\nhello"));
+    handle.wait();
+}
+
+#[test]
+pub fn shutdown_should_close_idle_connections_right_away() {
+    let mut app = slow_server();
+    let addr = &app.local_addr().unwrap().to_string();
+    app.config().idle_timeout = Some(Duration::from_secs(60));
+    let handle = app.spawn();
+
+    let mut stream = connect(addr);
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = [0; 256];
+    let read = stream.read(&mut response).unwrap();
+    assert!(response[..read].ends_with(b"\r\n\r\nhello"));
+
+    let start = Instant::now();
+    handle.shutdown();
+    handle.wait();
+    let mut rest = Vec::new();
+    stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+#[test]
+pub fn shutdown_should_close_connections_still_busy_after_the_timeout() {
+    let mut app = slow_server();
+    let addr = &app.local_addr().unwrap().to_string();
+    app.config().shutdown_timeout = Duration::from_millis(200);
+    let handle = app.spawn();
+
+    let mut stream = connect(addr);
+    stream
+        .write_all(b"GET /forever HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut buf = [0; 256];
+    assert!(stream.read(&mut buf).unwrap() > 0);
+
+    let start = Instant::now();
+    handle.shutdown();
+    handle.wait();
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200));
+    assert!(elapsed < Duration::from_secs(2));
+    // The stream ends without its last chunk.
+    let mut rest = Vec::new();
+    let _ = stream.read_to_end(&mut rest);
+    assert!(!rest.ends_with(b"0\r\n\r\n"));
+}
+
+#[cfg(unix)]
+#[test]
+pub fn shutdown_should_follow_sigterm() {
+    let app = slow_server();
+    let addr = &app.local_addr().unwrap().to_string();
+    let handle = app.spawn();
+    handle.shutdown_on_signals().unwrap();
+
+    unsafe { libc::raise(libc::SIGTERM) };
+    handle.wait();
+    assert_stopped(addr);
+}
+
+#[test]
+pub fn listening_should_leave_the_routes_with_the_application() {
+    let mut app = slow_server();
+    let handle = app.shutdown_handle();
+    thread::scope(|scope| {
+        scope.spawn(|| app.listen());
+        handle.shutdown();
+    });
+    assert!(matches!(
+        app.router().find(&RequestMethod::GET, "/slow"),
+        RouteMatch::Found { .. }
+    ));
+}
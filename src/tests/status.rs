@@ -0,0 +1,98 @@
+use espresso::{
+    error::HttpError,
+    espresso::Espresso,
+    request::EspressoRequest,
+    response::{EspressoResponse, EspressoResponseError},
+    status::StatusCode,
+};
+
+use super::support::{send_raw, serve};
+
+#[test]
+pub fn status_codes_should_know_their_reason_phrase_and_class() {
+    assert_eq!(StatusCode::NOT_FOUND.canonical_reason(), Some("Not Found"));
+    assert_eq!(
+        StatusCode::new(511).unwrap().canonical_reason(),
+        Some("Network Authentication Required")
+    );
+    assert_eq!(StatusCode::new(299).unwrap().canonical_reason(), None);
+
+    assert!(StatusCode::CONTINUE.is_informational());
+    assert!(StatusCode::NO_CONTENT.is_success());
+    assert!(StatusCode::SEE_OTHER.is_redirection());
+    assert!(StatusCode::TOO_MANY_REQUESTS.is_client_error());
+    assert!(StatusCode::BAD_GATEWAY.is_server_error());
+    assert!(StatusCode::new(599).unwrap().is_server_error());
+    assert!(!StatusCode::new(600).unwrap().is_server_error());
+    assert!(!StatusCode::OK.is_client_error());
+
+    assert_eq!(
+        StatusCode::try_from(404usize).unwrap(),
+        StatusCode::NOT_FOUND
+    );
+    assert_eq!(StatusCode::NOT_FOUND, 404);
+    assert_eq!(StatusCode::NOT_FOUND.to_string(), "404");
+}
+
+#[test]
+pub fn status_codes_outside_100_to_999_should_be_rejected() {
+    for code in [0usize, 99, 1000, 70000] {
+        assert!(
+            matches!(
+                StatusCode::try_from(code),
+                Err(EspressoResponseError::InvalidStatusCode(c)) if c == code
+            ),
+            "{code} should be rejected"
+        );
+    }
+    assert!(StatusCode::new(100).is_ok());
+    assert!(StatusCode::new(999).is_ok());
+
+    let mut response = EspressoResponse::new();
+    assert!(response.try_status(42).is_err());
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.message, "OK");
+}
+
+#[test]
+pub fn responses_should_carry_the_reason_phrase_of_their_status() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/missing",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.status(404);
+        },
+    );
+    app.get(
+        "/custom",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.set_status(StatusCode::SERVICE_UNAVAILABLE);
+            res.reason("Back\r\nSoon");
+        },
+    );
+    app.get(
+        "/unregistered",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.status(299);
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+    let response = send_raw(addr, "GET /custom HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 503 BackSoon\r\n"));
+
+    let response = send_raw(
+        addr,
+        "GET /unregistered HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.starts_with("HTTP/1.1 299 \r\n"));
+
+    // Errors can't carry a status that isn't one.
+    assert!(matches!(
+        HttpError::try_new(42, "no such status"),
+        Err(EspressoResponseError::InvalidStatusCode(42))
+    ));
+}
@@ -0,0 +1,271 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    sync::{mpsc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use espresso::{
+    builder::EspressoBuilder,
+    espresso::Espresso,
+    request::EspressoRequest,
+    response::{EspressoResponse, ResponseWriter},
+    sse::Event,
+};
+
+use super::support::{send_raw, serve};
+
+/// Reads from `stream` until what was read ends with `end`.
+fn read_until(stream: &mut TcpStream, received: &mut String, end: &str) {
+    let mut buf = [0; 1024];
+    while !received.ends_with(end) {
+        let read = stream.read(&mut buf).unwrap();
+        assert!(read > 0, "connection closed after {received:?}");
+        received.push_str(std::str::from_utf8(&buf[..read]).unwrap());
+    }
+}
+
+#[test]
+pub fn streamed_bodies_should_reach_the_client_while_being_written() {
+    let (next_row, rows) = mpsc::channel::<&'static str>();
+    let rows = Mutex::new(Some(rows));
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/export",
+        move |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            let rows = rows.lock().unwrap().take().unwrap();
+            res.set_header("Content-Type", "text/csv");
+            res.stream(move |out| {
+                out.write_all(b"id\n")?;
+                for row in rows {
+                    out.write_all(row.as_bytes())?;
+                }
+                Ok(())
+            });
+        },
+    );
+    let addr = &serve(app);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(b"GET /export HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut received = String::new();
+    // The headers and the first row arrive while the handler still waits for the next row.
+    read_until(&mut stream, &mut received, "3\r\nid\n\r\n");
+    assert!(received.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(received.contains("Transfer-Encoding: chunked\r\n"));
+
+    next_row.send("1\n").unwrap();
+    read_until(&mut stream, &mut received, "2\r\n1\n\r\n");
+    drop(next_row);
+    read_until(&mut stream, &mut received, "\r\n0\r\n\r\n");
+
+    // The connection stays usable after the last chunk.
+    stream
+        .write_all(b"GET /missing HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut received = String::new();
+    read_until(&mut stream, &mut received, "\r\n\r\n");
+    assert!(received.starts_with("HTTP/1.1 404"));
+}
+
+#[test]
+pub fn streamed_bodies_should_end_with_the_connection_for_http_1_0() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/report",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.stream(|out| out.write_all(b"progressive report"));
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(addr, "GET /report HTTP/1.0\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(!response.contains("Transfer-Encoding"));
+    assert!(response.ends_with("\r\n\r\nprogressive report"));
+
+    let response = send_raw(addr, "HEAD /report HTTP/1.0\r\n\r\n");
+    assert!(response.contains("Connection: close\r\n"));
+    assert!(!response.contains("Transfer-Encoding"));
+    assert!(response.ends_with("\r\n\r\n"));
+}
+
+#[test]
+pub fn event_streams_should_be_written_in_the_event_stream_format() {
+    let request = EspressoRequest::try_from(
+        "GET /events HTTP/1.1\r\nHost: localhost\r\nLast-Event-ID: 41\r\n\r\n".as_bytes(),
+    )
+    .unwrap();
+    let mut response = EspressoResponse::new();
+    response.sse(&request, |sse| {
+        let resumed = format!("after {}", sse.last_event_id().unwrap_or("nothing"));
+        sse.retry(Duration::from_secs(3))?;
+        sse.send(&Event::new("line 1\nline 2").event("update").id("42"))?;
+        sse.send(&Event::new("a").event("x\ny").id("4\r\n3"))?;
+        sse.comment("ping")?;
+        sse.data(&resumed)
+    });
+    let mut writer = ResponseWriter::new(Vec::new());
+    writer.write_response(response).unwrap();
+    assert_eq!(
+        String::from_utf8(writer.get_ref().clone()).unwrap(),
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\
+         Content-Type: text/event-stream\r\nCache-Control: no-cache\r\n\r\n\
+         D\r\nretry: 3000\n\n\r\n\
+         30\r\nevent: update\ndata: line 1\ndata: line 2\nid: 42\n\n\r\n\
+         1A\r\nevent: xy\ndata: a\nid: 43\n\n\r\n\
+         8\r\n: ping\n\n\r\n\
+         10\r\ndata: after 41\n\n\r\n\
+         0\r\n\r\n"
+    );
+}
+
+#[test]
+pub fn event_streams_should_not_hold_workers_and_should_notice_disconnects() {
+    let (done, disconnects) = mpsc::channel::<bool>();
+    let done = Mutex::new(done);
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/events",
+        move |req: &EspressoRequest, res: &mut EspressoResponse| {
+            let done = done.lock().unwrap().clone();
+            res.sse(req, move |sse| {
+                let (events, received) = mpsc::channel();
+                events.send(Event::new("hello").id("1")).unwrap();
+                let result = sse.relay(&received, Duration::from_millis(20));
+                drop(events);
+                let _ = done.send(result.is_err());
+                result
+            });
+        },
+    );
+    app.get(
+        "/ping",
+        |_req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send("pong");
+        },
+    );
+    let addr = &serve(app);
+
+    // More open streams than the pool has workers.
+    let mut streams = Vec::new();
+    for _ in 0..110 {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut received = String::new();
+        read_until(&mut stream, &mut received, "data: hello\nid: 1\n\n\r\n");
+        assert!(received.contains("Content-Type: text/event-stream\r\n"));
+        streams.push(stream);
+    }
+    let response = send_raw(addr, "GET /ping HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(response.ends_with("pong"));
+
+    let mut stream = streams.pop().unwrap();
+    let mut received = String::new();
+    read_until(&mut stream, &mut received, ": keep-alive\n\n\r\n");
+    drop(stream);
+    drop(streams);
+    for _ in 0..110 {
+        assert!(disconnects.recv_timeout(Duration::from_secs(5)).unwrap());
+    }
+}
+
+#[test]
+pub fn long_lived_responses_over_the_limit_should_get_a_503() {
+    let mut app = EspressoBuilder::new()
+        .max_long_lived(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+    app.get(
+        "/events",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.sse(req, |sse| {
+                sse.data("hello")?;
+                // Until the client goes away.
+                loop {
+                    thread::sleep(Duration::from_millis(20));
+                    sse.comment("ping")?;
+                }
+            });
+        },
+    );
+    let addr = &serve(app);
+    let open = |addr: &str| {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        stream
+    };
+
+    let mut first = open(addr);
+    let mut received = String::new();
+    read_until(&mut first, &mut received, "data: hello\n\n\r\n");
+    let response = send_raw(addr, "GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(
+        response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"),
+        "{response}"
+    );
+
+    // The place is given back once the stream ends.
+    drop(first);
+    let start = Instant::now();
+    loop {
+        let mut stream = open(addr);
+        let mut status = [0; 12];
+        stream.read_exact(&mut status).unwrap();
+        if &status == b"HTTP/1.1 200" {
+            break;
+        }
+        assert!(start.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+pub fn head_requests_to_event_streams_should_not_hold_a_long_lived_place() {
+    let mut app = EspressoBuilder::new()
+        .max_long_lived(1)
+        .bind("127.0.0.1:0")
+        .unwrap();
+    app.get(
+        "/events",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.sse(req, |sse| loop {
+                thread::sleep(Duration::from_millis(20));
+                sse.comment("ping")?;
+            });
+        },
+    );
+    app.get("/", |_req: &EspressoRequest, res: &mut EspressoResponse| {
+        res.send("index");
+    });
+    let addr = &serve(app);
+
+    // Takes the only place.
+    let mut first = TcpStream::connect(addr).unwrap();
+    first
+        .write_all(b"GET /events HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut received = String::new();
+    read_until(&mut first, &mut received, ": ping\n\n\r\n");
+
+    // HEAD gets the headers of the stream right away, and the connection carries on.
+    let response = send_raw(
+        addr,
+        "HEAD /events HTTP/1.1\r\nHost: localhost\r\n\r\nGET / HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    let (head, rest) = response.split_once("\r\n\r\n").unwrap();
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(
+        head.contains("Content-Type: text/event-stream"),
+        "{response}"
+    );
+    assert!(rest.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(rest.ends_with("\r\n\r\nindex"), "{response}");
+}
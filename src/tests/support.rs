@@ -0,0 +1,25 @@
+use std::{
+    io::{Read, Write},
+    net::{Shutdown, TcpStream},
+    thread,
+};
+
+use espresso::espresso::Espresso;
+
+/// Runs `app` on a background thread and returns the address it listens on. The listener is
+/// already bound, so requests can be sent right away.
+pub fn serve(mut app: Espresso) -> String {
+    let addr = app.local_addr().unwrap().to_string();
+    thread::spawn(move || app.listen());
+    addr
+}
+
+/// Writes `raw` to `addr`, half-closes the connection and returns everything the server sent back.
+pub fn send_raw(addr: &str, raw: impl AsRef<[u8]>) -> String {
+    let mut stream = TcpStream::connect(addr).expect("Couldn't connect to the test server.");
+    stream.write_all(raw.as_ref()).unwrap();
+    stream.shutdown(Shutdown::Write).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
@@ -0,0 +1,129 @@
+use espresso::{
+    error::EspressoRequestError, espresso::Espresso, request::EspressoRequest,
+    response::EspressoResponse, router::Router, url::Query,
+};
+
+use super::support::{send_raw, serve};
+
+fn parse(target: &str) -> Result<EspressoRequest, EspressoRequestError> {
+    EspressoRequest::try_from(
+        format!("GET {target} HTTP/1.1\r\nHost: localhost\r\n\r\n").as_bytes(),
+    )
+}
+
+#[test]
+pub fn query_should_decode_repeated_names_and_plus_signs() {
+    let query = Query::parse("q=rust+web%21&tag=a&&tag=b&flag&empty=&caf%C3%A9=cr%C3%A8me");
+    assert_eq!(query.get("q"), Some("rust web!"));
+    assert_eq!(query.get_all("tag").collect::<Vec<_>>(), vec!["a", "b"]);
+    assert_eq!(query.get("flag"), Some(""));
+    assert_eq!(query.get("empty"), Some(""));
+    assert_eq!(query.get("café"), Some("crème"));
+    assert!(query.contains("flag"));
+    assert!(!query.contains("missing"));
+    assert_eq!(query.iter().count(), 6);
+}
+
+#[test]
+pub fn query_should_tolerate_invalid_escapes_and_utf8() {
+    let query = Query::parse("a=100%&b=%zz&c=%FF%FE&d=%2B");
+    assert_eq!(query.get("a"), Some("100%"));
+    assert_eq!(query.get("b"), Some("%zz"));
+    assert_eq!(query.get("c"), Some("\u{FFFD}\u{FFFD}"));
+    assert_eq!(query.get("d"), Some("+"));
+}
+
+#[test]
+pub fn target_should_be_split_into_path_and_query() {
+    let request = parse("/search/caf%C3%A9?q=x&q=y#fragment").unwrap();
+    assert_eq!(request.resource, "/search/caf%C3%A9?q=x&q=y#fragment");
+    assert_eq!(request.path(), "/search/café");
+    assert_eq!(
+        request.query().get_all("q").collect::<Vec<_>>(),
+        vec!["x", "y"]
+    );
+
+    let request = parse("/plain").unwrap();
+    assert_eq!(request.path(), "/plain");
+    assert!(request.query().is_empty());
+
+    let request = parse("http://example.com/absolute?x=1").unwrap();
+    assert_eq!(request.path(), "/absolute");
+    assert_eq!(request.query().get("x"), Some("1"));
+}
+
+#[test]
+pub fn path_should_be_normalized() {
+    assert_eq!(parse("/a/./b/../c").unwrap().path(), "/a/c");
+    assert_eq!(parse("/a/%2e%2E/b").unwrap().path(), "/b");
+    assert_eq!(parse("/../../etc").unwrap().path(), "/etc");
+    assert_eq!(parse("/a/b/..").unwrap().path(), "/a/");
+    // An encoded slash stays inside its segment.
+    assert_eq!(parse("/files/a%2Fb").unwrap().path(), "/files/a%2Fb");
+
+    for target in ["/bad%FF", "relative", "/a%C3"] {
+        assert!(
+            matches!(
+                parse(target),
+                Err(EspressoRequestError::MalformedRequest(_))
+            ),
+            "{target:?} should be rejected"
+        );
+    }
+}
+
+#[test]
+pub fn routing_should_use_the_normalized_path_only() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.get(
+        "/search",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send(&format!(
+                "searching {};",
+                req.query().get("q").unwrap_or("")
+            ));
+        },
+    );
+    let mut admin = Router::new();
+    admin.get(
+        "/:name",
+        |req: &EspressoRequest, res: &mut EspressoResponse| {
+            res.send(&format!(
+                "{} at {};",
+                req.params().get("name").unwrap_or(""),
+                req.path()
+            ));
+        },
+    );
+    app.mount("/admin", admin);
+    app.middleware_at(
+        "/admin",
+        |req: &mut EspressoRequest,
+         res: &mut EspressoResponse,
+         next: espresso::middleware::Next| {
+            res.set_header("X-Admin", "checked");
+            next.run(req, res)
+        },
+    );
+    let addr = &serve(app);
+
+    let response = send_raw(
+        addr,
+        "GET /search?q=a+b HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.ends_with("searching a b;"));
+
+    let response = send_raw(
+        addr,
+        "GET /admin/caf%C3%A9 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.ends_with("café at /café;"));
+
+    // Dot segments can't be used to reach the mounted router without going through its middleware.
+    let response = send_raw(
+        addr,
+        "GET /public/../admin/users?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n",
+    );
+    assert!(response.contains("X-Admin: checked\r\n"));
+    assert!(response.ends_with("users at /users;"));
+}
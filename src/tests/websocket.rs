@@ -0,0 +1,368 @@
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    thread,
+    time::Duration,
+};
+
+use espresso::{
+    espresso::Espresso,
+    request::EspressoRequest,
+    websocket::{CloseCode, CloseFrame, Message, WebSocket},
+};
+
+use super::support::{send_raw, serve};
+
+const FIN: u8 = 0x80;
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// The upgrade request of RFC 6455 §1.2, for `path`.
+fn upgrade_request(path: &str) -> String {
+    format!(
+        "GET {path} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    )
+}
+
+/// Reads the head of a response alone, as frames may follow it right away.
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0; 1];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+/// A client that writes frames byte by byte as a test describes them, so it can break the
+/// protocol on purpose.
+struct Client {
+    stream: TcpStream,
+}
+
+impl Client {
+    /// Connects to `addr` and opens a WebSocket on `path`.
+    fn connect(addr: &str, path: &str) -> Client {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream.write_all(upgrade_request(path).as_bytes()).unwrap();
+        let head = read_head(&mut stream);
+        assert!(
+            head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+            "{head}"
+        );
+        Client { stream }
+    }
+
+    /// Sends a masked frame whose first byte is `first`: the FIN and reserved bits and the opcode.
+    fn send_frame(&mut self, first: u8, payload: &[u8]) {
+        let mask = [0x37, 0xFA, 0x21, 0x3D];
+        let mut frame = vec![first];
+        match payload.len() {
+            length @ 0..=125 => frame.push(0x80 | length as u8),
+            length @ 126..=0xFFFF => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(
+            payload
+                .iter()
+                .enumerate()
+                .map(|(ind, byte)| byte ^ mask[ind % 4]),
+        );
+        self.stream.write_all(&frame).unwrap();
+    }
+
+    /// Reads a frame of the server, returning its first byte and its payload.
+    fn read_frame(&mut self) -> (u8, Vec<u8>) {
+        let mut head = [0; 2];
+        self.stream.read_exact(&mut head).unwrap();
+        assert_eq!(head[1] & 0x80, 0, "server frames are never masked");
+        let length = match head[1] {
+            126 => {
+                let mut length = [0; 2];
+                self.stream.read_exact(&mut length).unwrap();
+                u16::from_be_bytes(length) as usize
+            }
+            127 => {
+                let mut length = [0; 8];
+                self.stream.read_exact(&mut length).unwrap();
+                u64::from_be_bytes(length) as usize
+            }
+            length => length as usize,
+        };
+        let mut payload = vec![0; length];
+        self.stream.read_exact(&mut payload).unwrap();
+        (head[0], payload)
+    }
+
+    /// Reads the close frame of the server, returns its code, and checks the connection ends.
+    fn expect_close(&mut self) -> Option<u16> {
+        let (first, payload) = self.read_frame();
+        assert_eq!(first, FIN | CLOSE);
+        let mut rest = Vec::new();
+        self.stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty(), "data after the close frame: {rest:?}");
+        match payload.as_slice() {
+            [] => None,
+            [high, low, ..] => Some(u16::from_be_bytes([*high, *low])),
+            _ => panic!("close frame with a 1 byte payload"),
+        }
+    }
+}
+
+/// Frames to send, as the first byte and the payload of each.
+type Frames = &'static [(u8, &'static [u8])];
+
+/// Serves a WebSocket on `/echo` that echoes text and binary messages until the client closes
+/// the connection or breaks the protocol. Returns the address of the server.
+fn echo_server(max_message_size: usize) -> String {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.config().max_message_size = max_message_size;
+    app.ws("/echo", |_req: &EspressoRequest, mut socket: WebSocket| {
+        while let Ok(message) = socket.recv() {
+            match message {
+                Message::Text(_) | Message::Binary(_) => socket.send(message).unwrap(),
+                Message::Close(_) => return,
+                Message::Ping(_) | Message::Pong(_) => {}
+            }
+        }
+    });
+    serve(app)
+}
+
+#[test]
+pub fn websocket_handshake_should_follow_rfc_6455() {
+    let addr = &echo_server(1 << 20);
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream
+        .write_all(upgrade_request("/echo").as_bytes())
+        .unwrap();
+    let head = read_head(&mut stream);
+    assert!(
+        head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"),
+        "{head}"
+    );
+    assert!(head.contains("Upgrade: websocket\r\n"));
+    assert!(head.contains("Connection: Upgrade\r\n"));
+    // The accept key of the example in RFC 6455 §1.3.
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    assert!(!head.contains("Content-Length"));
+
+    let plain = send_raw(addr, "GET /echo HTTP/1.1\r\nHost: localhost\r\n\r\n");
+    assert!(
+        plain.starts_with("HTTP/1.1 426 Upgrade Required\r\n"),
+        "{plain}"
+    );
+    assert!(plain.contains("Upgrade: websocket\r\n"));
+
+    let old_version = send_raw(
+        addr,
+        upgrade_request("/echo").replace("Version: 13", "Version: 8"),
+    );
+    assert!(old_version.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+    assert!(old_version.contains("Sec-WebSocket-Version: 13\r\n"));
+
+    for key in [
+        "",
+        "c2hvcnQ=",
+        "dGhlIHNhbXBsZSBub25jZQ",
+        "dGhlIHNhbXBsZSBub25jZR==",
+    ] {
+        let bad_key = send_raw(
+            addr,
+            upgrade_request("/echo").replace("dGhlIHNhbXBsZSBub25jZQ==", key),
+        );
+        assert!(bad_key.starts_with("HTTP/1.1 400"), "{key}: {bad_key}");
+    }
+
+    let http_1_0 = send_raw(
+        addr,
+        upgrade_request("/echo").replace("HTTP/1.1", "HTTP/1.0"),
+    );
+    assert!(http_1_0.starts_with("HTTP/1.1 400"), "{http_1_0}");
+}
+
+#[test]
+pub fn websocket_should_echo_messages_of_any_size_and_fragmentation() {
+    let addr = &echo_server(1 << 20);
+    let mut client = Client::connect(addr, "/echo");
+
+    // Payload lengths around the 7, 16 and 64 bit length encodings.
+    for length in [0, 125, 126, 127, 0xFFFF, 0x10000, 1 << 20] {
+        let text = "*".repeat(length);
+        client.send_frame(FIN | TEXT, text.as_bytes());
+        assert_eq!(client.read_frame(), (FIN | TEXT, text.into_bytes()));
+
+        let binary: Vec<u8> = (0..length).map(|ind| ind as u8).collect();
+        client.send_frame(FIN | BINARY, &binary);
+        assert_eq!(client.read_frame(), (FIN | BINARY, binary));
+    }
+
+    // Pings are answered with their payload, and unsolicited pongs are ignored.
+    client.send_frame(FIN | PONG, b"unsolicited");
+    client.send_frame(FIN | PING, &[0xFE; 125]);
+    assert_eq!(client.read_frame(), (FIN | PONG, vec![0xFE; 125]));
+
+    // Fragmented messages come back whole, with control frames answered in between.
+    client.send_frame(TEXT, b"frag");
+    client.send_frame(CONTINUATION, b"");
+    client.send_frame(FIN | PING, b"between");
+    client.send_frame(CONTINUATION, "mented \u{1F600}".as_bytes());
+    client.send_frame(FIN | CONTINUATION, b"!");
+    assert_eq!(client.read_frame(), (FIN | PONG, b"between".to_vec()));
+    assert_eq!(
+        client.read_frame(),
+        (FIN | TEXT, "fragmented \u{1F600}!".as_bytes().to_vec())
+    );
+
+    // A code point split across fragments is only checked once the message is whole.
+    let emoji = "\u{1F600}".as_bytes();
+    client.send_frame(TEXT, &emoji[..1]);
+    client.send_frame(FIN | CONTINUATION, &emoji[1..]);
+    assert_eq!(client.read_frame(), (FIN | TEXT, emoji.to_vec()));
+
+    // The close handshake echoes the code.
+    let mut close = CloseCode::GOING_AWAY.0.to_be_bytes().to_vec();
+    close.extend_from_slice(b"bye");
+    client.send_frame(FIN | CLOSE, &close);
+    assert_eq!(client.expect_close(), Some(1001));
+
+    let mut client = Client::connect(addr, "/echo");
+    client.send_frame(FIN | CLOSE, b"");
+    assert_eq!(client.expect_close(), None);
+}
+
+#[test]
+pub fn websocket_should_close_connections_that_break_the_protocol() {
+    let addr = &echo_server(1024);
+
+    let cases: &[(&str, Frames, u16)] = &[
+        ("reserved bit 1", &[(FIN | 0x40 | TEXT, b"rsv")], 1002),
+        ("reserved bit 2", &[(FIN | 0x20 | BINARY, b"rsv")], 1002),
+        ("reserved bit 3", &[(FIN | 0x10 | PING, b"rsv")], 1002),
+        ("reserved data opcode", &[(FIN | 0x3, b"")], 1002),
+        ("reserved control opcode", &[(FIN | 0xB, b"")], 1002),
+        ("ping over 125 bytes", &[(FIN | PING, &[0; 126])], 1002),
+        (
+            "fragmented ping",
+            &[(PING, b"a"), (FIN | CONTINUATION, b"b")],
+            1002,
+        ),
+        ("continuation first", &[(FIN | CONTINUATION, b"a")], 1002),
+        (
+            "message within a message",
+            &[(TEXT, b"a"), (FIN | TEXT, b"b")],
+            1002,
+        ),
+        (
+            "invalid UTF-8",
+            &[(FIN | TEXT, b"\xCE\xBA\xE1\xBD\xB9\xED\xA0\x80")],
+            1007,
+        ),
+        (
+            "truncated UTF-8",
+            &[(TEXT, b"a"), (FIN | CONTINUATION, b"\xF0\x9F")],
+            1007,
+        ),
+        ("message too big", &[(FIN | BINARY, &[0; 1025])], 1009),
+        (
+            "fragments too big",
+            &[(BINARY, &[0; 1000]), (FIN | CONTINUATION, &[0; 25])],
+            1009,
+        ),
+        ("close with 1 byte", &[(FIN | CLOSE, b"\x03")], 1002),
+        ("close code 0", &[(FIN | CLOSE, b"\x00\x00")], 1002),
+        ("close code 999", &[(FIN | CLOSE, b"\x03\xE7")], 1002),
+        ("close code 1005", &[(FIN | CLOSE, b"\x03\xED")], 1002),
+        ("close code 1006", &[(FIN | CLOSE, b"\x03\xEE")], 1002),
+        ("close code 1016", &[(FIN | CLOSE, b"\x03\xF8")], 1002),
+        ("close code 5000", &[(FIN | CLOSE, b"\x13\x88")], 1002),
+        (
+            "invalid UTF-8 reason",
+            &[(FIN | CLOSE, b"\x03\xE8\xFF")],
+            1007,
+        ),
+    ];
+    for (name, frames, code) in cases {
+        let mut client = Client::connect(addr, "/echo");
+        for (first, payload) in *frames {
+            client.send_frame(*first, payload);
+        }
+        assert_eq!(client.expect_close(), Some(*code), "{name}");
+    }
+
+    // Client frames have to be masked.
+    let mut client = Client::connect(addr, "/echo");
+    client
+        .stream
+        .write_all(&[FIN | TEXT, 2, b'h', b'i'])
+        .unwrap();
+    assert_eq!(client.expect_close(), Some(1002));
+
+    // Codes for applications are echoed like the registered ones.
+    let mut client = Client::connect(addr, "/echo");
+    client.send_frame(FIN | CLOSE, b"\x0F\xA0done");
+    assert_eq!(client.expect_close(), Some(4000));
+}
+
+#[test]
+pub fn websocket_senders_should_send_from_other_threads() {
+    let mut app = Espresso::bind("127.0.0.1:0").unwrap();
+    app.ws("/ticks", |req: &EspressoRequest, mut socket: WebSocket| {
+        let count: u8 = req
+            .query()
+            .get("count")
+            .and_then(|count| count.parse().ok())
+            .unwrap();
+        let sender = socket.sender();
+        let ticker = thread::spawn(move || {
+            for tick in 0..count {
+                sender.send(Message::Binary(vec![tick])).unwrap();
+            }
+            sender
+                .send_fragmented(Message::Text("last tick".to_string()), 4)
+                .unwrap();
+            sender.close(CloseCode::NORMAL, "done").unwrap();
+            // Nothing goes out after the close frame.
+            assert!(sender.send(Message::Text("late".to_string())).is_err());
+        });
+        assert_eq!(
+            socket.recv().unwrap(),
+            Message::Close(Some(CloseFrame {
+                code: CloseCode::NORMAL,
+                reason: String::new(),
+            }))
+        );
+        ticker.join().unwrap();
+    });
+    let addr = &serve(app);
+
+    let mut client = Client::connect(addr, "/ticks?count=3");
+    for tick in 0..3 {
+        assert_eq!(client.read_frame(), (FIN | BINARY, vec![tick]));
+    }
+    assert_eq!(client.read_frame(), (TEXT, b"last".to_vec()));
+    assert_eq!(client.read_frame(), (CONTINUATION, b" tic".to_vec()));
+    assert_eq!(client.read_frame(), (FIN | CONTINUATION, b"k".to_vec()));
+    assert_eq!(client.read_frame(), (FIN | CLOSE, b"\x03\xE8done".to_vec()));
+    client.send_frame(FIN | CLOSE, b"\x03\xE8");
+    let mut rest = Vec::new();
+    client.stream.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
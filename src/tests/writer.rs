@@ -0,0 +1,324 @@
+use std::io::{self, Cursor, IoSlice, Write};
+
+use espresso::{
+    body::Body,
+    response::{EspressoResponse, ResponseWriter},
+    status::StatusCode,
+};
+
+fn serialize_bytes(response: EspressoResponse) -> Vec<u8> {
+    let mut writer = ResponseWriter::new(Vec::new());
+    writer.write_response(response).unwrap();
+    writer.get_ref().clone()
+}
+
+fn serialize(response: EspressoResponse) -> String {
+    String::from_utf8(serialize_bytes(response)).unwrap()
+}
+
+fn response(status: usize, headers: &[(&str, &str)], body: &str) -> EspressoResponse {
+    let mut response = EspressoResponse::new();
+    response.status(status);
+    for (name, value) in headers {
+        response.append_header(name, value);
+    }
+    response.send(body);
+    response
+}
+
+/// Accepts at most three bytes per call, so every response takes many partial writes.
+struct Trickle(Vec<u8>);
+
+impl Write for Trickle {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(3);
+        self.0.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        let buf = bufs
+            .iter()
+            .find(|buf| !buf.is_empty())
+            .map_or(&[][..], |buf| &buf[..]);
+        self.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+pub fn writer_should_serialize_responses_byte_for_byte() {
+    let cases: Vec<(EspressoResponse, &str)> = vec![
+        (
+            EspressoResponse::new(),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        ),
+        (
+            response(200, &[], "hello"),
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello",
+        ),
+        (
+            response(404, &[("Content-Type", "text/plain")], "nope"),
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 4\r\nContent-Type: text/plain\r\n\r\nnope",
+        ),
+        (
+            response(
+                200,
+                &[("Set-Cookie", "a=1"), ("X-Id", "7"), ("Set-Cookie", "b=2")],
+                "",
+            ),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\nSet-Cookie: a=1\r\nX-Id: 7\r\nSet-Cookie: b=2\r\n\r\n",
+        ),
+        (
+            response(200, &[("Content-Length", "12")], ""),
+            "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        ),
+        (
+            response(204, &[("Content-Length", "4"), ("X-Id", "7")], "gone"),
+            "HTTP/1.1 204 No Content\r\nX-Id: 7\r\n\r\n",
+        ),
+        (
+            response(103, &[("Link", "</style.css>; rel=preload")], ""),
+            "HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\n\r\n",
+        ),
+        (
+            response(304, &[("ETag", "\"v1\"")], "cached"),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n",
+        ),
+        (
+            response(299, &[], "x"),
+            "HTTP/1.1 299 \r\nContent-Length: 1\r\n\r\nx",
+        ),
+    ];
+    for (response, expected) in cases {
+        assert_eq!(serialize(response), expected);
+    }
+}
+
+#[test]
+pub fn writer_should_keep_headers_from_breaking_the_framing() {
+    let mut injected = response(
+        200,
+        &[
+            ("X-Value", "a\r\nX-Injected: 1"),
+            ("Bad Name", "dropped"),
+            ("X-Lf", "b\nc\0d"),
+            ("", "dropped"),
+        ],
+        "ok",
+    );
+    injected.reason("Fine\r\n\r\n");
+    injected.message.push_str("\r\nX-Raw: 1");
+    assert_eq!(
+        serialize(injected),
+        "HTTP/1.1 200 FineX-Raw: 1\r\nContent-Length: 2\r\nX-Value: aX-Injected: 1\r\nX-Lf: bcd\r\n\r\nok"
+    );
+}
+
+#[test]
+pub fn writer_should_finish_partial_writes_and_reuse_its_buffers() {
+    let mut first = response(200, &[("Content-Type", "text/plain")], "first body");
+    first.set_status(StatusCode::CREATED);
+    let mut second = EspressoResponse::new();
+    second.set_body(Body::reader(Cursor::new("chunked body")));
+    let third = response(500, &[], "");
+
+    let mut writer = ResponseWriter::new(Trickle(Vec::new()));
+    writer.write_response(first).unwrap();
+    writer.write_response(second).unwrap();
+    writer.write_response(third).unwrap();
+    assert_eq!(
+        String::from_utf8(writer.get_ref().0.clone()).unwrap(),
+        "HTTP/1.1 201 Created\r\nContent-Length: 10\r\nContent-Type: text/plain\r\n\r\nfirst body\
+         HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nC\r\nchunked body\r\n0\r\n\r\n\
+         HTTP/1.1 500 Internal Server Error\r\nContent-Length: 0\r\n\r\n"
+    );
+}
+
+#[test]
+pub fn writer_should_fail_when_the_connection_takes_nothing() {
+    struct Closed;
+    impl Write for Closed {
+        fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+            Ok(0)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut writer = ResponseWriter::new(Closed);
+    let err = writer.write_response(EspressoResponse::new()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+}
+
+#[test]
+pub fn writer_should_send_binary_bodies_as_they_are() {
+    let mut response = EspressoResponse::new();
+    response.set_header("Content-Type", "image/png");
+    response.send_bytes(&[0x89, b'P', b'N', b'G', 0x00, 0xff]);
+    let mut expected =
+        b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\nContent-Type: image/png\r\n\r\n".to_vec();
+    expected.extend_from_slice(&[0x89, b'P', b'N', b'G', 0x00, 0xff]);
+    assert_eq!(serialize_bytes(response), expected);
+}
+
+#[test]
+pub fn writer_should_frame_readers_by_length_or_in_chunks() {
+    let mut sized = EspressoResponse::new();
+    sized.set_body(Body::sized_reader(Cursor::new("hello world"), 5));
+    assert_eq!(
+        serialize(sized),
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+    );
+
+    let mut appended = EspressoResponse::new();
+    appended.set_body(Body::sized_reader(Cursor::new("hello"), 5));
+    appended.send(" world");
+    assert_eq!(
+        serialize(appended),
+        "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world"
+    );
+
+    let mut chunked = EspressoResponse::new();
+    chunked.set_body(Body::reader(Cursor::new("a".repeat(20000))));
+    assert_eq!(
+        serialize(chunked),
+        format!(
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4000\r\n{}\r\nE20\r\n{}\r\n0\r\n\r\n",
+            "a".repeat(16384),
+            "a".repeat(3616)
+        )
+    );
+
+    let mut empty = EspressoResponse::new();
+    empty.set_body(Body::reader(io::empty()));
+    assert_eq!(
+        serialize(empty),
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n"
+    );
+
+    let mut short = EspressoResponse::new();
+    short.set_body(Body::sized_reader(Cursor::new("abc"), 10));
+    let err = ResponseWriter::new(Vec::new())
+        .write_response(short)
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+pub fn writer_should_hold_bodies_to_their_content_length() {
+    let mut bytes = response(200, &[("Content-Length", "12")], "hello");
+    bytes.set_header("X-Id", "7");
+    assert_eq!(
+        serialize(bytes),
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Id: 7\r\n\r\nhello"
+    );
+
+    let mut sized = EspressoResponse::new();
+    sized.set_header("Content-Length", "2");
+    sized.set_body(Body::sized_reader(Cursor::new("hello"), 5));
+    assert_eq!(
+        serialize(sized),
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+    );
+
+    // A body of unknown length is sent as long as declared, and fails if it isn't.
+    let mut declared = EspressoResponse::new();
+    declared.set_header("Content-Length", "5");
+    declared.set_body(Body::reader(Cursor::new("hello")));
+    assert_eq!(
+        serialize(declared),
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+    );
+    for (length, body) in [("10", "short"), ("3", "too long"), ("five", "")] {
+        let mut response = EspressoResponse::new();
+        response.set_header("Content-Length", length);
+        response.stream(move |out| out.write_all(body.as_bytes()));
+        assert!(ResponseWriter::new(Vec::new())
+            .write_response(response)
+            .is_err());
+    }
+}
+
+#[test]
+pub fn writer_should_send_serialized_values_with_their_content_type() {
+    let mut response = EspressoResponse::new();
+    response.serialize(&json::parse(r#"{"id": 7, "tags": ["a"]}"#).unwrap());
+    assert_eq!(
+        serialize(response),
+        "HTTP/1.1 200 OK\r\nContent-Length: 21\r\nContent-Type: application/json\r\n\r\n{\"id\":7,\"tags\":[\"a\"]}"
+    );
+
+    let mut response = EspressoResponse::new();
+    response.serialize("plain");
+    assert_eq!(
+        serialize(response),
+        "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nContent-Type: text/plain; charset=utf-8\r\n\r\nplain"
+    );
+}
+
+#[test]
+pub fn writer_should_frame_streams_by_length_or_in_chunks() {
+    let mut chunked = EspressoResponse::new();
+    chunked.set_header("Content-Type", "text/csv");
+    chunked.stream(|out| {
+        out.write_all(b"id,name\n")?;
+        out.write_all(b"")?;
+        let (id, name) = (1, "espresso");
+        out.write_all(format!("{id},{name}\n").as_bytes())
+    });
+    assert_eq!(
+        serialize(chunked),
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Type: text/csv\r\n\r\n\
+         8\r\nid,name\n\r\nB\r\n1,espresso\n\r\n0\r\n\r\n"
+    );
+
+    let mut sized = EspressoResponse::new();
+    sized.set_body(Body::sized_stream(5, |out| out.write_all(b"hello")));
+    sized.send("!");
+    assert_eq!(
+        serialize(sized),
+        "HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nhello!"
+    );
+
+    let mut closing = EspressoResponse::new();
+    closing.set_header("Connection", "close");
+    closing.stream(|out| out.write_all(b"until the end"));
+    assert_eq!(
+        serialize(closing),
+        "HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nuntil the end"
+    );
+}
+
+#[test]
+pub fn writer_should_fail_streams_that_break_their_length() {
+    for (length, body) in [(10, "short"), (3, "too long")] {
+        let mut response = EspressoResponse::new();
+        response.set_body(Body::sized_stream(length, move |out| {
+            out.write_all(body.as_bytes())
+        }));
+        let mut writer = ResponseWriter::new(Vec::new());
+        assert!(writer.write_response(response).is_err());
+        // The headers have been sent, but never more of the body than its length.
+        let sent = String::from_utf8(writer.get_ref().clone()).unwrap();
+        assert!(sent.starts_with(&format!("HTTP/1.1 200 OK\r\nContent-Length: {length}\r\n")));
+        assert!(sent.len() - sent.find("\r\n\r\n").unwrap() - 4 <= length as usize);
+    }
+
+    let mut failing = EspressoResponse::new();
+    failing.stream(|out| {
+        out.write_all(b"partial")?;
+        Err(io::Error::other("report failed"))
+    });
+    let mut writer = ResponseWriter::new(Vec::new());
+    assert!(writer.write_response(failing).is_err());
+    // Without the last chunk, the client can tell the body is incomplete.
+    assert!(!String::from_utf8(writer.get_ref().clone())
+        .unwrap()
+        .ends_with("0\r\n\r\n"));
+}
@@ -1,9 +1,23 @@
-type Job = Box<dyn FnOnce() + Send + 'static>;
+pub(crate) type Job = Box<dyn FnOnce() + Send + 'static>;
 
 pub mod pigeonhole_threads;
 pub mod stream_threads;
 
 pub trait TPool {
     fn new(size: usize) -> Self;
-    fn exec<Fn>(&self, task: Fn) -> () where Fn: FnOnce() + Send + 'static;
+    fn exec<Fn>(&self, task: Fn) -> ()
+    where
+        Fn: FnOnce() + Send + 'static;
+}
+
+/// Runs jobs on a [`TPool`], whichever implementation it is, so the server can hold any of them.
+/// The event loops of the epoll engine share it, hence `Sync`.
+pub(crate) trait Executor: Send + Sync {
+    fn execute(&self, job: Job);
+}
+
+impl<P: TPool + Send + Sync> Executor for P {
+    fn execute(&self, job: Job) {
+        self.exec(job);
+    }
 }
@@ -1,97 +1,114 @@
-use std::{ sync::{ mpsc, Mutex, MutexGuard }, thread };
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{mpsc, Arc, Condvar, Mutex},
+    thread,
+};
 
-use super::{ Job, TPool };
+use super::{Job, TPool};
+use crate::request::lock;
 
-struct LockingJob<'a> {
-    job: Job,
-    thread_guard: MutexGuard<'a, Worker>,
-}
 /// ## Info
 /// The thread pool holds a number of threads to process concurrently
-/// This implementation is a first-available-first-service thread pool, where the threads are checked for availability linearly
-/// ## Todo:
-/// - Finish the executing method
-/// - Implement a available thread vector for pigeon-hole thread scheduling (tracking threads availability instead of blind search)
+/// This implementation is a pigeon-hole thread pool: every job is handed to a worker of its own,
+/// taken from a vector of the available workers instead of searching for one
 /// ## Panics
 /// If the number of threads is <= 0
 pub struct ThreadPool {
-    workers: Vec<Mutex<Worker>>,
+    workers: Vec<Worker>,
+    available: Arc<Available>,
+}
+
+/// The ids of the workers waiting for a job.
+struct Available {
+    ids: Mutex<Vec<usize>>,
+    freed: Condvar,
 }
 
 struct Worker {
-    id: usize,
-    thread: thread::JoinHandle<()>,
+    thread: Option<thread::JoinHandle<()>>,
     work_chann: Option<mpsc::Sender<Job>>,
 }
-/// A worker-mutex based thread pool implementation
+/// A worker-vector based thread pool implementation
 /// ## Pros:
 /// - Health-checkable (% of workers busy) for scaling and sharding if necessary
+/// - A job never waits behind another one
 /// ## Cons:
-/// Checks incur too much runtime cost due to high Mutex usage.
+/// `exec` blocks while every worker is busy.
 impl TPool for ThreadPool {
     fn new(threads_num: usize) -> ThreadPool {
         assert!(threads_num > 0);
 
-        let mut workers: Vec<Mutex<Worker>> = vec![];
-        for i in 0..threads_num {
-            workers.push(Mutex::new(Worker::new(i)));
-        }
-        println!("Created {threads_num} workers");
-        ThreadPool { workers }
+        // Popped from the back, so the first workers are handed the first jobs.
+        let available = Arc::new(Available {
+            ids: Mutex::new((0..threads_num).rev().collect()),
+            freed: Condvar::new(),
+        });
+        let workers = (0..threads_num)
+            .map(|id| Worker::new(id, &available))
+            .collect();
+        ThreadPool { workers, available }
     }
 
-    fn exec<Fn>(&self, task: Fn) -> () where Fn: FnOnce() + Send + 'static {
-        let mut task_box = Some(Box::new(task));
-        loop {
-            for worker in &self.workers {
-                match worker.try_lock() {
-                    Ok(wk) => {
-                        match &wk.work_chann {
-                            Some(chann) => {
-                                match task_box.take() {
-                                    Some(task) => {
-                                        chann
-                                            .send(task)
-                                            .expect("The worker didn't work properly...");
-                                    }
-                                    _ => {
-                                        return;
-                                    }
-                                }
-                                println!("Worker {} received work", wk.id);
-                            }
-                            _ => (),
-                        }
-                    }
-                    Err(_) => {
-                        println!("A worker was busy, continuing");
-                        continue;
-                    }
+    fn exec<Fn>(&self, task: Fn)
+    where
+        Fn: FnOnce() + Send + 'static,
+    {
+        let mut ids = lock(&self.available.ids);
+        let id = loop {
+            match ids.pop() {
+                Some(id) => break id,
+                None => {
+                    ids = self
+                        .available
+                        .freed
+                        .wait(ids)
+                        .unwrap_or_else(|poisoned| poisoned.into_inner());
                 }
             }
+        };
+        drop(ids);
+        if let Some(chann) = &self.workers[id].work_chann {
+            chann
+                .send(Box::new(task))
+                .expect("The worker didn't work properly...");
         }
     }
 }
 
+impl ThreadPool {
+    /// How many workers are waiting for a job.
+    pub fn idle_workers(&self) -> usize {
+        lock(&self.available.ids).len()
+    }
+}
+
 impl Worker {
-    pub fn new(id: usize) -> Worker {
+    fn new(id: usize, available: &Arc<Available>) -> Worker {
         let (tx, rx): (mpsc::Sender<Job>, mpsc::Receiver<Job>) = mpsc::channel();
+        let available = Arc::clone(available);
         let thread: thread::JoinHandle<()> = thread::spawn(move || {
             while let Ok(work) = rx.recv() {
-                work();
+                // Panics are reported by the panic hook; the worker carries on with the next job.
+                let _ = panic::catch_unwind(AssertUnwindSafe(work));
+                lock(&available.ids).push(id);
+                available.freed.notify_one();
             }
         });
         Worker {
-            id,
-            thread,
+            thread: Some(thread),
             work_chann: Some(tx),
         }
     }
 }
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        for worker in &mut self.workers.drain(..) {
-            drop(worker.lock().unwrap().work_chann.take());
+        for worker in &mut self.workers {
+            drop(worker.work_chann.take());
+        }
+        for worker in &mut self.workers {
+            if let Some(thread) = worker.thread.take() {
+                let _ = thread.join();
+            }
         }
     }
 }
@@ -1,48 +1,79 @@
-use std::{ sync::{ mpsc::{ self, Receiver, Sender }, Arc, Mutex }, thread::{ self, JoinHandle } };
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
 
-use super::{ Job, TPool };
+use super::{Job, TPool};
+use crate::request::lock;
+
+type SharedReceiver = Arc<Mutex<Receiver<Job>>>;
+type Workers = Arc<Mutex<Vec<Worker>>>;
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
+    workers: Workers,
     work_sender: Option<Sender<Job>>,
+    /// Set once the pool is dropped, after which workers that die aren't replaced.
+    stopping: Arc<AtomicBool>,
 }
 impl TPool for ThreadPool {
     fn new(size: usize) -> ThreadPool {
-        let (tx, rx): (
-            Sender<Box<dyn FnOnce() + Send + 'static>>,
-            Receiver<Box<dyn FnOnce() + Send + 'static>>,
-        ) = mpsc::channel();
-        let work_receiver: Arc<Mutex<Receiver<Job>>> = Arc::new(Mutex::new(rx));
-        let mut workers: Vec<Worker> = Vec::new();
+        let (tx, rx): (Sender<Job>, Receiver<Job>) = mpsc::channel();
+        let work_receiver: SharedReceiver = Arc::new(Mutex::new(rx));
+        let workers: Workers = Arc::new(Mutex::new(Vec::with_capacity(size)));
+        let stopping = Arc::new(AtomicBool::new(false));
         for i in 0..size {
-            workers.push(Worker::new(i, &work_receiver));
+            let worker = Worker::new(i, &work_receiver, &workers, &stopping);
+            lock(&workers).push(worker);
+        }
+        ThreadPool {
+            workers,
+            work_sender: Some(tx),
+            stopping,
         }
-        ThreadPool { workers, work_sender: Some(tx) }
     }
-    fn exec<Fn>(&self, work: Fn) where Fn: FnOnce() + Send + 'static {
-        match &self.work_sender {
-            Some(sender) => {
-                sender.send(Box::new(work)).unwrap();
-            }
-            _ => (),
+    fn exec<Fn>(&self, work: Fn)
+    where
+        Fn: FnOnce() + Send + 'static,
+    {
+        if let Some(sender) = &self.work_sender {
+            sender.send(Box::new(work)).unwrap();
         }
     }
 }
+
 struct Worker {
     id: usize,
     thread: JoinHandle<()>,
 }
 impl Worker {
-    pub fn new(id: usize, recv: &Arc<Mutex<Receiver<Job>>>) -> Worker {
-        let recv: Arc<Mutex<Receiver<Box<dyn FnOnce() + Send + 'static>>>> = Arc::clone(recv);
+    pub fn new(
+        id: usize,
+        recv: &SharedReceiver,
+        workers: &Workers,
+        stopping: &Arc<AtomicBool>,
+    ) -> Worker {
+        let recv: SharedReceiver = Arc::clone(recv);
+        let sentinel = Sentinel {
+            id,
+            recv: Arc::clone(&recv),
+            workers: Arc::clone(workers),
+            stopping: Arc::clone(stopping),
+        };
 
         let thread = thread::spawn(move || {
+            let _sentinel = sentinel;
             loop {
-                // This acquires and unwraps the value of the Mutex lock fyi
-                let message = recv.lock().unwrap().recv();
+                // The guard is a temporary, so the lock is released before the job runs.
+                let message = lock(&recv).recv();
                 match message {
                     Ok(job) => {
-                        job();
+                        // Panics are reported by the panic hook; the worker carries on with the next job.
+                        let _ = panic::catch_unwind(AssertUnwindSafe(job));
                     }
                     Err(_) => {
                         break;
@@ -54,17 +85,43 @@ impl Worker {
         Worker { id, thread }
     }
 }
+
+/// Lives on a worker thread and replaces the worker if the thread unwinds, so the pool keeps its size.
+struct Sentinel {
+    id: usize,
+    recv: SharedReceiver,
+    workers: Workers,
+    stopping: Arc<AtomicBool>,
+}
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+        // The flag is checked with the workers locked, so a dropped pool either joins the
+        // replacement or never gets one.
+        let mut workers = lock(&self.workers);
+        if self.stopping.load(Ordering::SeqCst) {
+            return;
+        }
+        if let Some(worker) = workers.iter_mut().find(|worker| worker.id == self.id) {
+            *worker = Worker::new(self.id, &self.recv, &self.workers, &self.stopping);
+        }
+    }
+}
+
 impl Drop for ThreadPool {
     fn drop(&mut self) {
         drop(self.work_sender.take());
 
-        for worker in &mut self.workers.drain(..) {
-            match worker.thread.join() {
-                Err(_) => {
-                    panic!("Unable to stop thread {}", worker.id);
-                }
-                _ => (),
-            }
+        let workers: Vec<Worker> = {
+            let mut workers = lock(&self.workers);
+            self.stopping.store(true, Ordering::SeqCst);
+            workers.drain(..).collect()
+        };
+        for worker in workers {
+            // A worker that died has nothing left to stop, and dropping the pool mustn't panic.
+            let _ = worker.thread.join();
         }
     }
 }
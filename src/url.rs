@@ -0,0 +1,140 @@
+use crate::error::EspressoRequestError;
+
+/// The parameters of a query string such as `?q=rust+web&tag=a&tag=b`, decoded and in the order
+/// they were sent. A name may appear more than once.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Query(Vec<(String, String)>);
+
+impl Query {
+    /// Parses a query string, without its leading `?`, as `application/x-www-form-urlencoded`.
+    ///
+    /// `+` stands for a space and percent-encoded bytes are decoded. Bytes that don't form valid
+    /// UTF-8 are replaced with U+FFFD, and a `%` that doesn't start an escape is kept as it is.
+    pub fn parse(query: &str) -> Query {
+        let pairs = query
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (decode_component(name), decode_component(value))
+            })
+            .collect();
+        Query(pairs)
+    }
+
+    /// The first value of `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of `name`, in order.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0
+            .iter()
+            .filter(move |(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.iter().any(|(key, _)| key == name)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+fn decode_component(component: &str) -> String {
+    let bytes = percent_decode(component.replace('+', " ").as_bytes(), |_| true);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Decodes the `%XX` escapes in `input` for which `decode` returns `true`, leaving the others and
+/// any `%` not followed by two hex digits as they are.
+fn percent_decode(input: &[u8], decode: impl Fn(u8) -> bool) -> Vec<u8> {
+    let mut output: Vec<u8> = Vec::with_capacity(input.len());
+    let mut ind = 0;
+    while ind < input.len() {
+        if let [b'%', high, low, ..] = input[ind..] {
+            if let (Some(high), Some(low)) = (hex_value(high), hex_value(low)) {
+                let byte = high << 4 | low;
+                if decode(byte) {
+                    output.push(byte);
+                    ind += 3;
+                    continue;
+                }
+            }
+        }
+        output.push(input[ind]);
+        ind += 1;
+    }
+    output
+}
+
+fn hex_value(digit: u8) -> Option<u8> {
+    (digit as char).to_digit(16).map(|value| value as u8)
+}
+
+/// Splits a request target into its normalized path and its query string, if it has one.
+///
+/// The path is percent-decoded, except for `%2F`, which would otherwise turn into a segment
+/// separator, and `.` and `..` segments are resolved as in RFC 3986 §5.2.4, so a request can't
+/// reach around the prefix of a mounted router or of middleware. The absolute form used towards
+/// proxies is accepted, and the asterisk form of `OPTIONS *` is kept as `*`.
+pub(crate) fn split_target(target: &str) -> Result<(String, Option<String>), EspressoRequestError> {
+    let target = target.split_once('#').map_or(target, |(target, _)| target);
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    if path == "*" {
+        return Ok((path.to_string(), query));
+    }
+
+    let path = match path.split_once("://") {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") =>
+        {
+            rest.find('/').map_or("/", |start| &rest[start..])
+        }
+        _ => path,
+    };
+    if !path.starts_with('/') {
+        return Err(EspressoRequestError::MalformedRequest(
+            "Request target must be an absolute path.".to_string(),
+        ));
+    }
+
+    let mut segments: Vec<String> = Vec::new();
+    let parts: Vec<&str> = path[1..].split('/').collect();
+    for (ind, part) in parts.iter().enumerate() {
+        let decoded = String::from_utf8(percent_decode(part.as_bytes(), |byte| byte != b'/'))
+            .map_err(|_| {
+                EspressoRequestError::MalformedRequest(
+                    "Request path is not valid UTF-8.".to_string(),
+                )
+            })?;
+        let last = ind == parts.len() - 1;
+        match decoded.as_str() {
+            "." if last => segments.push(String::new()),
+            "." => {}
+            ".." => {
+                segments.pop();
+                if last {
+                    segments.push(String::new());
+                }
+            }
+            _ => segments.push(decoded),
+        }
+    }
+    Ok((format!("/{}", segments.join("/")), query))
+}
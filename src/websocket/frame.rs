@@ -0,0 +1,112 @@
+use std::io::{self, IoSlice, Read, Write};
+
+use crate::{error::EspressoWebSocketError, response::write_all_vectored};
+
+use super::CloseCode;
+
+pub(crate) const OP_CONTINUATION: u8 = 0x0;
+pub(crate) const OP_TEXT: u8 = 0x1;
+pub(crate) const OP_BINARY: u8 = 0x2;
+pub(crate) const OP_CLOSE: u8 = 0x8;
+pub(crate) const OP_PING: u8 = 0x9;
+pub(crate) const OP_PONG: u8 = 0xA;
+
+/// The largest payload of a control frame (RFC 6455 §5.5).
+pub(crate) const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// One frame, with its payload already unmasked.
+pub(crate) struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+}
+
+/// Reads a frame sent by a client, whose payload can't exceed `max_payload`.
+///
+/// Frames that break RFC 6455 §5 are rejected: unmasked frames, reserved bits without an
+/// extension that defines them, and fragmented or oversized control frames.
+pub(crate) fn read_frame(
+    reader: &mut impl Read,
+    max_payload: usize,
+) -> Result<Frame, EspressoWebSocketError> {
+    let mut head = [0u8; 2];
+    reader.read_exact(&mut head)?;
+    let fin = head[0] & 0x80 != 0;
+    let opcode = head[0] & 0x0F;
+    if head[0] & 0x70 != 0 {
+        return Err(protocol_error("Reserved bits are set."));
+    }
+    if head[1] & 0x80 == 0 {
+        return Err(protocol_error("Client frames must be masked."));
+    }
+
+    let length = match head[1] & 0x7F {
+        126 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0u8; 8];
+            reader.read_exact(&mut length)?;
+            let length = u64::from_be_bytes(length);
+            if length >> 63 != 0 {
+                return Err(protocol_error("Payload length is out of range."));
+            }
+            length
+        }
+        length => length as u64,
+    };
+    if opcode & 0x8 != 0 && (!fin || length > MAX_CONTROL_PAYLOAD as u64) {
+        return Err(protocol_error(
+            "Control frames can't be fragmented or longer than 125 bytes.",
+        ));
+    }
+    if length > max_payload as u64 {
+        return Err(EspressoWebSocketError::ProtocolError(
+            CloseCode::MESSAGE_TOO_BIG,
+            "Message is too big.".to_string(),
+        ));
+    }
+
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec![0u8; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (ind, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[ind % 4];
+    }
+    Ok(Frame {
+        fin,
+        opcode,
+        payload,
+    })
+}
+
+/// Writes an unmasked frame, as servers send them.
+pub(crate) fn write_frame(
+    writer: &mut impl Write,
+    fin: bool,
+    opcode: u8,
+    payload: &[u8],
+) -> io::Result<()> {
+    let mut head = Vec::with_capacity(10);
+    head.push(if fin { 0x80 } else { 0 } | opcode);
+    match payload.len() {
+        length @ 0..=125 => head.push(length as u8),
+        length @ 126..=0xFFFF => {
+            head.push(126);
+            head.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            head.push(127);
+            head.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    write_all_vectored(writer, &mut [IoSlice::new(&head), IoSlice::new(payload)])?;
+    writer.flush()
+}
+
+pub(crate) fn protocol_error(message: &str) -> EspressoWebSocketError {
+    EspressoWebSocketError::ProtocolError(CloseCode::PROTOCOL_ERROR, message.to_string())
+}
@@ -0,0 +1,115 @@
+/// Appended to the client's key before hashing it, as RFC 6455 §1.3 specifies.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// The `Sec-WebSocket-Accept` answering a `Sec-WebSocket-Key`.
+pub(crate) fn accept_key(key: &str) -> String {
+    base64_encode(&sha1(format!("{key}{GUID}").as_bytes()))
+}
+
+/// Whether `key` is a valid `Sec-WebSocket-Key`: 16 bytes, base64-encoded.
+pub(crate) fn is_valid_key(key: &str) -> bool {
+    base64_decode(key).is_some_and(|nonce| nonce.len() == 16)
+}
+
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (ind, word) in block.chunks_exact(4).enumerate() {
+            words[ind] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for ind in 16..80 {
+            words[ind] = (words[ind - 3] ^ words[ind - 8] ^ words[ind - 14] ^ words[ind - 16])
+                .rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (ind, word) in words.iter().enumerate() {
+            let (f, k) = match ind {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let value = group.iter().enumerate().fold(0u32, |value, (ind, byte)| {
+            value | (*byte as u32) << (16 - 8 * ind)
+        });
+        for ind in 0..4 {
+            if ind <= group.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * ind) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes padded base64, rejecting anything that isn't in its canonical form.
+fn base64_decode(encoded: &str) -> Option<Vec<u8>> {
+    let encoded = encoded.as_bytes();
+    if !encoded.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    for (group_ind, group) in encoded.chunks(4).enumerate() {
+        let last = group_ind == encoded.len() / 4 - 1;
+        let padding = group.iter().rev().take_while(|byte| **byte == b'=').count();
+        if padding > 2 || (padding > 0 && !last) {
+            return None;
+        }
+        let mut value = 0u32;
+        for byte in &group[..4 - padding] {
+            let digit = ALPHABET.iter().position(|letter| letter == byte)?;
+            value = value << 6 | digit as u32;
+        }
+        value <<= 6 * padding as u32;
+        let bytes = &value.to_be_bytes()[1..4 - padding];
+        // Bits beyond the last byte have to be zero.
+        if value.to_be_bytes()[4 - padding..]
+            .iter()
+            .any(|byte| *byte != 0)
+        {
+            return None;
+        }
+        decoded.extend_from_slice(bytes);
+    }
+    Some(decoded)
+}
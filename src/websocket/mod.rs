@@ -0,0 +1,362 @@
+use std::{
+    io,
+    net::{Shutdown, TcpStream},
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    error::{EspressoWebSocketError, HttpError},
+    request::{lock, EspressoRequest, EspressoStream, RequestMethod, SharedReader},
+    response::EspressoResponse,
+    status::StatusCode,
+};
+
+mod frame;
+mod handshake;
+
+use frame::{
+    protocol_error, read_frame, write_frame, MAX_CONTROL_PAYLOAD, OP_BINARY, OP_CLOSE,
+    OP_CONTINUATION, OP_PING, OP_PONG, OP_TEXT,
+};
+
+/// Why a connection is closed, as sent in a close frame (RFC 6455 §7.4).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    pub const NORMAL: CloseCode = CloseCode(1000);
+    pub const GOING_AWAY: CloseCode = CloseCode(1001);
+    pub const PROTOCOL_ERROR: CloseCode = CloseCode(1002);
+    pub const UNSUPPORTED_DATA: CloseCode = CloseCode(1003);
+    pub const INVALID_PAYLOAD: CloseCode = CloseCode(1007);
+    pub const POLICY_VIOLATION: CloseCode = CloseCode(1008);
+    pub const MESSAGE_TOO_BIG: CloseCode = CloseCode(1009);
+    pub const MANDATORY_EXTENSION: CloseCode = CloseCode(1010);
+    pub const INTERNAL_ERROR: CloseCode = CloseCode(1011);
+
+    /// Whether the code may be sent in a close frame: a registered code, or one from the ranges
+    /// for libraries and applications. 1005, 1006 and 1015 only describe a close locally.
+    pub fn is_allowed(self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+}
+
+/// The code and reason of a close frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// A close frame, without a payload if it carries no code.
+    Close(Option<CloseFrame>),
+}
+
+/// Answers `request` with the WebSocket opening handshake (RFC 6455 §4.2) and, once it is sent,
+/// hands the connection to `handler` on a thread of its own.
+///
+/// Requests that aren't a valid upgrade are answered instead: `426 Upgrade Required` when the
+/// upgrade or the protocol version is missing, and `400 Bad Request` for a malformed key or a
+/// request that can't be upgraded.
+pub fn accept(
+    request: &EspressoRequest,
+    response: &mut EspressoResponse,
+    handler: impl FnOnce(&EspressoRequest, WebSocket) + Send + 'static,
+) -> Result<(), HttpError> {
+    let headers = &request.headers;
+    if !headers.contains_token("Upgrade", "websocket")
+        || !headers.contains_token("Connection", "upgrade")
+    {
+        response.set_status(StatusCode::UPGRADE_REQUIRED);
+        response.set_header("Upgrade", "websocket");
+        response.set_header("Connection", "Upgrade");
+        response.send("This resource can only be reached through a WebSocket.");
+        return Ok(());
+    }
+    if request.method != RequestMethod::GET || request.protocol_ver != "HTTP/1.1" {
+        return Err(HttpError::bad_request(
+            "A WebSocket is opened with a GET request over HTTP/1.1.",
+        ));
+    }
+    if headers.get("Sec-WebSocket-Version") != Some("13") {
+        response.set_status(StatusCode::UPGRADE_REQUIRED);
+        response.set_header("Sec-WebSocket-Version", "13");
+        response.send("Only version 13 of the WebSocket protocol is supported.");
+        return Ok(());
+    }
+    let key = match headers.get("Sec-WebSocket-Key") {
+        Some(key) if handshake::is_valid_key(key) => key,
+        _ => return Err(HttpError::bad_request("Invalid Sec-WebSocket-Key.")),
+    };
+
+    response.set_status(StatusCode::SWITCHING_PROTOCOLS);
+    response.set_header("Upgrade", "websocket");
+    response.set_header("Connection", "Upgrade");
+    response.set_header("Sec-WebSocket-Accept", &handshake::accept_key(key));
+    response.upgrade = Some(Box::new(move |request, stream| {
+        handler(request, WebSocket::new(stream))
+    }));
+    Ok(())
+}
+
+/// A WebSocket connection to a client.
+///
+/// [`WebSocket::recv`] reads the messages of the client, putting fragmented messages back
+/// together and answering pings and closes as it goes. Messages can be sent from other threads
+/// through a [`WebSocketSender`]. Dropping the socket shuts the connection down.
+pub struct WebSocket {
+    reader: SharedReader,
+    sender: WebSocketSender,
+    max_message_size: usize,
+    /// The opcode and payload so far of a message whose last fragment hasn't arrived.
+    partial: Option<(u8, Vec<u8>)>,
+    closed: bool,
+}
+
+impl WebSocket {
+    fn new(stream: EspressoStream) -> WebSocket {
+        let (reader, tcp, config) = stream.into_parts();
+        WebSocket {
+            reader,
+            sender: WebSocketSender {
+                state: Arc::new(Mutex::new(SenderState {
+                    tcp,
+                    close_sent: false,
+                })),
+            },
+            max_message_size: config.max_message_size,
+            partial: None,
+            closed: false,
+        }
+    }
+
+    /// Waits for the next message of the client.
+    ///
+    /// Pings are answered with a pong before they are returned. When the client closes the
+    /// connection, its close frame is answered, the connection is shut down and the close
+    /// is returned; from then on `recv` fails with
+    /// [`EspressoWebSocketError::ConnectionClosed`]. A client that breaks the protocol gets
+    /// a close frame with the matching code and the error is returned.
+    pub fn recv(&mut self) -> Result<Message, EspressoWebSocketError> {
+        if self.closed {
+            return Err(EspressoWebSocketError::ConnectionClosed);
+        }
+        match self.read_message() {
+            Err(EspressoWebSocketError::ProtocolError(code, reason)) => {
+                let _ = self.sender.close(code, &reason);
+                self.shutdown();
+                Err(EspressoWebSocketError::ProtocolError(code, reason))
+            }
+            Err(err) => {
+                self.shutdown();
+                Err(err)
+            }
+            message => message,
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Message, EspressoWebSocketError> {
+        loop {
+            let limit =
+                self.max_message_size - self.partial.as_ref().map_or(0, |(_, data)| data.len());
+            let frame = read_frame(&mut *lock(&self.reader), limit)?;
+            match frame.opcode {
+                OP_TEXT | OP_BINARY if self.partial.is_some() => {
+                    return Err(protocol_error(
+                        "A new message started before the last one ended.",
+                    ));
+                }
+                OP_TEXT | OP_BINARY if frame.fin => {
+                    return into_message(frame.opcode, frame.payload)
+                }
+                OP_TEXT | OP_BINARY => self.partial = Some((frame.opcode, frame.payload)),
+                OP_CONTINUATION => {
+                    let Some((opcode, data)) = &mut self.partial else {
+                        return Err(protocol_error("No message to continue."));
+                    };
+                    data.extend_from_slice(&frame.payload);
+                    if frame.fin {
+                        let opcode = *opcode;
+                        let data = std::mem::take(data);
+                        self.partial = None;
+                        return into_message(opcode, data);
+                    }
+                }
+                OP_PING => {
+                    match self.sender.write(OP_PONG, &frame.payload) {
+                        Ok(()) | Err(EspressoWebSocketError::ConnectionClosed) => {}
+                        Err(err) => return Err(err),
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                OP_PONG => return Ok(Message::Pong(frame.payload)),
+                OP_CLOSE => {
+                    let close = parse_close(&frame.payload)?;
+                    // Echo the code, unless this side has started closing already.
+                    let echo = match &close {
+                        Some(close) => close.code.0.to_be_bytes().to_vec(),
+                        None => Vec::new(),
+                    };
+                    match self.sender.write(OP_CLOSE, &echo) {
+                        Ok(()) | Err(EspressoWebSocketError::ConnectionClosed) => {}
+                        Err(err) => return Err(err),
+                    }
+                    self.shutdown();
+                    return Ok(Message::Close(close));
+                }
+                _ => return Err(protocol_error("Reserved opcode.")),
+            }
+        }
+    }
+
+    /// Sends `message` to the client. See [`WebSocketSender::send`].
+    pub fn send(&self, message: Message) -> Result<(), EspressoWebSocketError> {
+        self.sender.send(message)
+    }
+
+    /// Starts the close handshake. See [`WebSocketSender::close`].
+    pub fn close(&self, code: CloseCode, reason: &str) -> Result<(), EspressoWebSocketError> {
+        self.sender.close(code, reason)
+    }
+
+    /// A handle for sending messages to the client from other threads.
+    pub fn sender(&self) -> WebSocketSender {
+        self.sender.clone()
+    }
+
+    fn shutdown(&mut self) {
+        self.closed = true;
+        let _ = lock(&self.sender.state).tcp.shutdown(Shutdown::Both);
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        if !self.closed {
+            self.shutdown();
+        }
+    }
+}
+
+fn into_message(opcode: u8, data: Vec<u8>) -> Result<Message, EspressoWebSocketError> {
+    if opcode == OP_BINARY {
+        return Ok(Message::Binary(data));
+    }
+    String::from_utf8(data).map(Message::Text).map_err(|_| {
+        EspressoWebSocketError::ProtocolError(
+            CloseCode::INVALID_PAYLOAD,
+            "Text message is not valid UTF-8.".to_string(),
+        )
+    })
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, EspressoWebSocketError> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(protocol_error("Close frame payload is too short.")),
+        [high, low, reason @ ..] => {
+            let code = CloseCode(u16::from_be_bytes([*high, *low]));
+            if !code.is_allowed() {
+                return Err(protocol_error("Close code is not allowed."));
+            }
+            let reason = String::from_utf8(reason.to_vec()).map_err(|_| {
+                EspressoWebSocketError::ProtocolError(
+                    CloseCode::INVALID_PAYLOAD,
+                    "Close reason is not valid UTF-8.".to_string(),
+                )
+            })?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+/// Sends messages on a [`WebSocket`]. Clones send on the same connection, one message at a time.
+#[derive(Clone)]
+pub struct WebSocketSender {
+    state: Arc<Mutex<SenderState>>,
+}
+
+struct SenderState {
+    tcp: TcpStream,
+    close_sent: bool,
+}
+
+impl WebSocketSender {
+    /// Sends `message` as a single frame.
+    ///
+    /// Fails with [`EspressoWebSocketError::ConnectionClosed`] once a close frame has been sent,
+    /// and with an I/O error of kind `InvalidInput` for a ping, pong or close whose payload is
+    /// longer than 125 bytes.
+    pub fn send(&self, message: Message) -> Result<(), EspressoWebSocketError> {
+        match message {
+            Message::Text(text) => self.write(OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => self.write(OP_BINARY, &data),
+            Message::Ping(data) => self.write(OP_PING, &data),
+            Message::Pong(data) => self.write(OP_PONG, &data),
+            Message::Close(None) => self.write(OP_CLOSE, &[]),
+            Message::Close(Some(close)) => self.close(close.code, &close.reason),
+        }
+    }
+
+    /// Sends a text or binary `message` in fragments of at most `fragment_size` bytes, so a
+    /// large message doesn't have to go out in one frame. Other messages are sent as they are.
+    pub fn send_fragmented(
+        &self,
+        message: Message,
+        fragment_size: usize,
+    ) -> Result<(), EspressoWebSocketError> {
+        let (opcode, data) = match &message {
+            Message::Text(text) => (OP_TEXT, text.as_bytes()),
+            Message::Binary(data) => (OP_BINARY, data.as_slice()),
+            _ => return self.send(message),
+        };
+        let mut state = lock(&self.state);
+        if state.close_sent {
+            return Err(EspressoWebSocketError::ConnectionClosed);
+        }
+        let mut fragments = data.chunks(fragment_size.max(1)).peekable();
+        let mut opcode = opcode;
+        if fragments.peek().is_none() {
+            return Ok(write_frame(&mut state.tcp, true, opcode, &[])?);
+        }
+        while let Some(fragment) = fragments.next() {
+            write_frame(&mut state.tcp, fragments.peek().is_none(), opcode, fragment)?;
+            opcode = OP_CONTINUATION;
+        }
+        Ok(())
+    }
+
+    /// Sends a close frame, after which nothing more can be sent. The reason is cut short to fit
+    /// in the frame. Keep calling [`WebSocket::recv`] until the client answers with its own close.
+    pub fn close(&self, code: CloseCode, reason: &str) -> Result<(), EspressoWebSocketError> {
+        let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+        while !reason.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut payload = code.0.to_be_bytes().to_vec();
+        payload.extend_from_slice(&reason.as_bytes()[..end]);
+        self.write(OP_CLOSE, &payload)
+    }
+
+    fn write(&self, opcode: u8, payload: &[u8]) -> Result<(), EspressoWebSocketError> {
+        if opcode & 0x8 != 0 && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Control frames can't be longer than 125 bytes.",
+            )
+            .into());
+        }
+        let mut state = lock(&self.state);
+        if state.close_sent {
+            return Err(EspressoWebSocketError::ConnectionClosed);
+        }
+        state.close_sent = opcode == OP_CLOSE;
+        Ok(write_frame(&mut state.tcp, true, opcode, payload)?)
+    }
+}